@@ -15,14 +15,21 @@ pub use crate::{
             self, stop_login, exit, sms_login, pwd_login, switch_cookie, scan_login, refresh_cookie
         },
         aria2c::{
-            self, push_back_queue, process_queue, toggle_pause, remove_task
+            self, push_back_queue, process_queue, toggle_pause, remove_task, retry_task, retry_all_failed, set_speed_limit,
+            move_task, set_priority, pause_item, resume_item, pause_all, resume_all,
+            get_queue_state, subscribe_progress, unsubscribe_progress, convert
         },
+        temp::{self, clean_temp},
+        disk,
+        downloader,
         ffmpeg,
+        native,
     },
     storage::{
         config::{self, rw_config},
         cookies,
         downloads,
+        queue,
     },
     shared,
     errors::{TauriResult, TauriError},
@@ -107,12 +114,14 @@ pub async fn xml_to_ass(app: tauri::AppHandle, secret: String, path: String, con
     write_binary(secret, input.to_string_lossy().into(), contents).await?;
     let result = app.shell().sidecar("DanmakuFactory")?
         .args(["-i", input.to_str().unwrap(), "-o", output.to_str().unwrap()])
-        .output().await?;
+        .output().await;
+    // Don't leave the input behind when DanmakuFactory fails
+    let _ = fs::remove_file(input).await;
+    let result = result?;
 
     log::info!("STDOUT: {:?}", String::from_utf8_lossy(&result.stdout));
     log::info!("STDERR: {:?}", String::from_utf8_lossy(&result.stderr));
     fs::rename(output, path).await?;
-    fs::remove_file(input).await?;
     Ok(())
 }
 
@@ -162,6 +171,10 @@ pub async fn init(app: tauri::AppHandle, secret: String) -> TauriResult<InitData
     login::get_extra_cookies().await?;
     shared::init_headers().await?;
     let downloads = downloads::load().await?;
+    aria2c::QUEUE_MANAGER.update(aria2c::QueueType::Waiting).await;
+    aria2c::QUEUE_MANAGER.update(aria2c::QueueType::Doing).await;
+    aria2c::QUEUE_MANAGER.update(aria2c::QueueType::Failed).await;
+    aria2c::QUEUE_MANAGER.update(aria2c::QueueType::Paused).await;
     let hash = env!("GIT_HASH").to_string();
     let version = app.package_info().version.to_string();
     Ok(InitData { version, hash, downloads })
@@ -5,7 +5,8 @@ pub mod shared;
 pub mod errors;
 
 use tauri_specta::{collect_commands, collect_events, Builder};
-use tauri::{async_runtime, Manager};
+use tauri::{async_runtime, Manager, RunEvent};
+use std::time::Duration;
 use commands::*;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -24,10 +25,13 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         .commands(collect_commands![
             stop_login, exit, sms_login, pwd_login, switch_cookie, scan_login, refresh_cookie, // Login
             ready, init, get_size, clean_cache, write_binary, xml_to_ass, rw_config, set_theme, // Essentials
-            push_back_queue, process_queue, toggle_pause, remove_task // Aria2c
+            push_back_queue, process_queue, toggle_pause, remove_task, retry_task, retry_all_failed, set_speed_limit,
+            move_task, set_priority, pause_item, resume_item, pause_all, resume_all,
+            get_queue_state, subscribe_progress, unsubscribe_progress, clean_temp, convert // Aria2c
         ])
         .events(collect_events![
-            config::Settings, shared::Headers, shared::SidecarError, services::aria2c::QueueEvent
+            config::Settings, shared::Headers, shared::SidecarError, services::aria2c::QueueEvent,
+            services::aria2c::Aria2Health, services::disk::DiskSpace
         ]);
 
     #[cfg(debug_assertions)] // <- Only export on non-release builds
@@ -81,7 +85,24 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
             });
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running BiliTools");
+        .build(tauri::generate_context!())
+        .expect("error while building BiliTools")
+        .run(|app, event| if let RunEvent::ExitRequested { api, .. } = event {
+            let mut shutting_down = shared::SHUTTING_DOWN.write().unwrap();
+            if *shutting_down {
+                return;
+            }
+            *shutting_down = true;
+            api.prevent_exit();
+            let app = app.clone();
+            async_runtime::spawn(async move {
+                // Outlasts every wait inside, 10s for merges, 2x3s of RPC and 5s for aria2c to exit
+                if tokio::time::timeout(Duration::from_secs(30), services::shutdown()).await.is_err() {
+                    log::warn!("Graceful shutdown timed out");
+                    shared::kill_children();
+                }
+                app.exit(0);
+            });
+        });
     Ok(())
 }
\ No newline at end of file
@@ -1,25 +1,41 @@
-use std::{collections::VecDeque, fs, net::{SocketAddr, TcpListener}, path::PathBuf, sync::{Arc, RwLock as StdRwLock}, time::Duration};
+use std::{collections::{HashMap, VecDeque}, fs, iter, net::{SocketAddr, TcpListener}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, AtomicU32, Ordering}, Arc, Mutex as StdMutex, RwLock as StdRwLock}, time::{Duration, Instant}};
 use tauri::{async_runtime::{self, Receiver}, http::StatusCode, ipc::Channel, Manager};
-use tauri_plugin_shell::{process::{CommandChild, CommandEvent}, ShellExt};
-use tokio::{sync::{mpsc, RwLock}, time::sleep};
+use tauri_plugin_shell::{process::CommandEvent, ShellExt};
+use tokio::{sync::{broadcast, mpsc, Mutex, Notify, RwLock}, time::{sleep, timeout}};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
 use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 use sea_orm::FromJsonQueryResult;
 use tauri_plugin_http::reqwest;
 use serde_json::{json, Value};
 use lazy_static::lazy_static;
+use futures_util::StreamExt;
 use tauri_specta::Event;
 use specta::Type;
 
 use crate::{
-    downloads, errors::TauriResult, ffmpeg, shared::{
-        get_app_handle, init_client_no_proxy, process_err, random_string, SidecarError, CONFIG, READY, SECRET, USER_AGENT, WORKING_PATH
-    }, TauriError
+    config::{DownloadBackend, TranscodeProfile, VerifyMode}, downloads, errors::TauriResult, ffmpeg, native, queue, shared::{
+        get_app_handle, has_child, init_client, kill_child, process_err, random_string,
+        track_child, untrack_child, SidecarError, CONFIG, READY, SECRET, SHUTTING_DOWN, USER_AGENT, WORKING_PATH
+    }, disk::{check_space, has_space, space_needs}, temp::{cleanup, temp_root}, TauriError,
+    downloader::{self, DownloadState, DownloadStatus, Downloader, StateChange}
 };
 
 lazy_static! {
     pub static ref QUEUE_MANAGER: QueueManager = QueueManager::new();
     static ref ARIA2C_PORT: Arc<StdRwLock<u16>> = Arc::new(StdRwLock::new(0));
+    static ref ARIA2C_RUNNING: AtomicBool = AtomicBool::new(false);
+    static ref ARIA2C_RESTARTING: AtomicBool = AtomicBool::new(false);
+    static ref ARIA2C_EXTERNAL: AtomicBool = AtomicBool::new(false);
+    static ref ARIA2C_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .no_proxy().build().unwrap();
+    pub static ref DOWNLOAD_EVENTS: DownloadChannels = DownloadChannels::new();
+    static ref ACTIVE_GIDS: StdRwLock<HashMap<String, (Arc<String>, DownloadBackend)>> = StdRwLock::new(HashMap::new());
+    static ref SCHEDULER_WAKE: Notify = Notify::new();
+    static ref SCHEDULER_ACTIVE: AtomicBool = AtomicBool::new(false);
+    static ref SCHEDULER_PAUSED: AtomicBool = AtomicBool::new(false);
+    static ref ITEM_PROGRESS: StdRwLock<HashMap<String, ItemProgress>> = StdRwLock::new(HashMap::new());
+    static ref CANCEL_TOKENS: StdRwLock<HashMap<String, Arc<CancelToken>>> = StdRwLock::new(HashMap::new());
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult, Type)]
@@ -29,6 +45,33 @@ pub struct QueueInfo {
     pub output: PathBuf,
     pub info: Arc<ArchiveInfo>,
     pub select: CurrentSelect,
+    #[serde(default)]
+    pub error: Option<QueueError>,
+    #[serde(default)]
+    pub speed_limit: Option<u64>,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub verification: Option<Verification>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct Verification {
+    pub size: u64,
+    pub expected: Option<u64>,
+    pub decoded: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct QueueError {
+    pub code: Option<isize>,
+    pub message: String,
+    #[serde(rename = "taskType")]
+    pub task_type: TaskType,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
@@ -44,6 +87,11 @@ pub struct Task {
     #[serde(rename = "taskType")]
     pub task_type: TaskType,
     pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub size: Option<u64>,
+    // The backend that owns gid, kept so that control never crosses over after a settings change
+    #[serde(default)]
+    pub backend: DownloadBackend,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
@@ -53,6 +101,53 @@ pub struct ArchiveInfo {
     pub ts: Timestamp,
     pub output_dir: String,
     pub filename: String,
+    #[serde(default)]
+    pub metadata: Option<MediaMetadata>,
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+    #[serde(default)]
+    pub subtitles: Vec<SubtitleTrack>,
+    #[serde(default)]
+    pub profile: Option<TranscodeProfile>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct Chapter {
+    pub title: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct SubtitleTrack {
+    pub lang: String,
+    pub title: String,
+    pub kind: SubtitleKind,
+    pub default: bool,
+    #[serde(default)]
+    pub burn: bool,
+    #[serde(default)]
+    pub data: Option<String>,
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleKind {
+    Srt,
+    Danmaku,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct MediaMetadata {
+    pub artist: Option<String>,
+    pub date: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub album: Option<String>,
+    pub episode: Option<u32>,
+    pub genre: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
@@ -63,12 +158,22 @@ pub struct CurrentSelect {
     pub fmt: i32,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
 #[serde(rename_all = "lowercase")]
 pub enum QueueType {
     Waiting,
     Doing,
     Complete,
+    Failed,
+    Paused,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum QueuePosition {
+    Top,
+    Bottom,
+    Index(usize),
 }
 
 #[derive(Clone, Serialize, Type, Event)]
@@ -83,6 +188,25 @@ pub enum QueueEvent {
     Complete {
         data: VecDeque<Arc<QueueInfo>>
     },
+    Failed {
+        data: VecDeque<Arc<QueueInfo>>
+    },
+    Paused {
+        data: VecDeque<Arc<QueueInfo>>
+    },
+}
+
+#[derive(Clone, Serialize, Type, Event)]
+#[serde(tag = "status")]
+pub enum Aria2Health {
+    Healthy {
+        port: u16,
+    },
+    Restarting {
+        attempt: u32,
+        delay: u64,
+        reason: String,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Type)]
@@ -101,10 +225,47 @@ pub enum DownloadEvent {
         content_length: u64,
         #[serde(rename = "chunkLength")]
         chunk_length: u64,
+        speed: u64,
+        eta: Option<u64>,
+        connections: u64,
+    },
+    Aggregate {
+        id: Arc<String>,
+        progress: f64,
+        remaining: u64,
+    },
+    Retrying {
+        id: Arc<String>,
+        gid: Arc<String>,
+        attempt: usize,
+        #[serde(rename = "maxAttempts")]
+        max_attempts: usize,
+        code: isize,
+        message: String,
     },
     Finished {
         id: Arc<String>,
         gid: Arc<String>,
+    },
+    Finalizing {
+        id: Arc<String>,
+        #[serde(rename = "contentLength")]
+        content_length: u64,
+        #[serde(rename = "chunkLength")]
+        chunk_length: u64,
+    },
+    Verifying {
+        id: Arc<String>,
+    },
+    Converting {
+        id: Arc<String>,
+        #[serde(rename = "contentLength")]
+        content_length: u64,
+        #[serde(rename = "chunkLength")]
+        chunk_length: u64,
+    },
+    Cancelled {
+        id: Arc<String>,
     }
 }
 
@@ -117,6 +278,55 @@ pub enum TaskType {
     Flac,
 }
 
+#[derive(Clone, Debug, Serialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStage {
+    Downloading,
+    Merging,
+    Converting,
+}
+
+impl From<&TaskType> for TaskStage {
+    fn from(task_type: &TaskType) -> Self {
+        match task_type {
+            TaskType::Video | TaskType::Audio => TaskStage::Downloading,
+            TaskType::Merge => TaskStage::Merging,
+            TaskType::Flac => TaskStage::Converting,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Type)]
+pub struct TaskState {
+    pub gid: String,
+    #[serde(rename = "taskType")]
+    pub task_type: TaskType,
+    #[serde(rename = "contentLength")]
+    pub content_length: u64,
+    #[serde(rename = "chunkLength")]
+    pub chunk_length: u64,
+    pub speed: u64,
+    pub finished: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Type)]
+pub struct ItemState {
+    pub stage: Option<TaskStage>,
+    pub progress: f64,
+    pub remaining: u64,
+    pub tasks: Vec<TaskState>,
+}
+
+#[derive(Clone, Debug, Serialize, Type)]
+pub struct QueueState {
+    pub waiting: VecDeque<Arc<QueueInfo>>,
+    pub doing: VecDeque<Arc<QueueInfo>>,
+    pub complete: VecDeque<Arc<QueueInfo>>,
+    pub failed: VecDeque<Arc<QueueInfo>>,
+    pub paused: VecDeque<Arc<QueueInfo>>,
+    pub progress: HashMap<String, ItemState>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Aria2Error {
     code: isize,
@@ -139,6 +349,25 @@ struct Aria2TellStatus {
     error: Option<Aria2Error>
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct Aria2TellActive {
+    id: String,
+    jsonrpc: String,
+    result: Option<Vec<Aria2TellStatusResult>>,
+    error: Option<Aria2Error>
+}
+
+#[derive(Debug, Deserialize)]
+struct Aria2Notification {
+    method: String,
+    params: Vec<Aria2NotificationParams>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Aria2NotificationParams {
+    gid: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Aria2TellStatusResult {
     gid: String,
@@ -170,10 +399,148 @@ struct Aria2TellStatusResult {
     error_message: Option<String>
 }
 
+impl From<Aria2TellStatusResult> for DownloadStatus {
+    fn from(value: Aria2TellStatusResult) -> Self {
+        let state = match value.status.as_str() {
+            "active" => DownloadState::Active,
+            "waiting" => DownloadState::Waiting,
+            "paused" => DownloadState::Paused,
+            "complete" => DownloadState::Complete,
+            "error" => DownloadState::Error,
+            _ => DownloadState::Removed,
+        };
+        Self {
+            gid: value.gid,
+            state,
+            total_length: value.total_length.parse().unwrap_or(0),
+            completed_length: value.completed_length.parse().unwrap_or(0),
+            download_speed: value.download_speed.parse().unwrap_or(0),
+            connections: value.connections.parse().unwrap_or(0),
+            error_code: value.error_code.and_then(|v| v.parse().ok()),
+            error_message: value.error_message,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TaskProgress {
+    task_type: TaskType,
+    content_length: u64,
+    chunk_length: u64,
+    speed: u64,
+    finished: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ItemProgress {
+    stage: Option<TaskType>,
+    tasks: HashMap<String, TaskProgress>,
+}
+
+impl TaskProgress {
+    fn new(task_type: TaskType) -> Self {
+        Self { task_type, content_length: 0, chunk_length: 0, speed: 0, finished: false }
+    }
+    fn fraction(&self) -> f64 {
+        if self.finished {
+            1.0
+        } else if self.content_length == 0 {
+            0.0
+        } else {
+            (self.chunk_length as f64 / self.content_length as f64).min(1.0)
+        }
+    }
+}
+
+fn aggregate(item: &ItemProgress) -> (f64, u64) {
+    fn mean(tasks: &[&TaskProgress]) -> f64 {
+        if tasks.is_empty() { return 1.0; }
+        tasks.iter().map(|v| v.fraction()).sum::<f64>() / tasks.len() as f64
+    }
+    let (downloads, post): (Vec<&TaskProgress>, Vec<&TaskProgress>) = item.tasks.values()
+        .partition(|v| matches!(v.task_type, TaskType::Video | TaskType::Audio));
+    let remaining = downloads.iter().filter(|v| !v.finished)
+        .map(|v| v.content_length.saturating_sub(v.chunk_length)).sum();
+    // Weigh by bytes once every stream size is known, otherwise fall back to per-task fractions
+    let download = if !downloads.is_empty() && downloads.iter().all(|v| v.content_length > 0) {
+        let total: u64 = downloads.iter().map(|v| v.content_length).sum();
+        let done: u64 = downloads.iter().map(|v|
+            if v.finished { v.content_length } else { v.chunk_length.min(v.content_length) }
+        ).sum();
+        done as f64 / total as f64
+    } else { mean(&downloads) };
+    let overall = if post.is_empty() { download } else { download * 0.9 + mean(&post) * 0.1 };
+    (overall * 100.0, remaining)
+}
+
+fn send_aggregate(id: &Arc<String>) {
+    let overall = ITEM_PROGRESS.read().unwrap().get(&**id).map(aggregate);
+    if let Some((progress, remaining)) = overall {
+        DOWNLOAD_EVENTS.send(DownloadEvent::Aggregate { id: id.clone(), progress, remaining });
+    }
+}
+
+pub fn send_progress(
+    id: Arc<String>,
+    gid: Arc<String>,
+    content_length: u64,
+    chunk_length: u64,
+    speed: u64,
+    connections: u64,
+) {
+    let eta = (speed > 0).then(|| content_length.saturating_sub(chunk_length) / speed);
+    if let Some(task) = ITEM_PROGRESS.write().unwrap()
+        .get_mut(&*id).and_then(|v| v.tasks.get_mut(&*gid))
+    {
+        task.content_length = content_length;
+        task.chunk_length = chunk_length;
+        task.speed = speed;
+    }
+    DOWNLOAD_EVENTS.send(DownloadEvent::Progress {
+        id: id.clone(), gid, content_length, chunk_length, speed, eta, connections,
+    });
+    send_aggregate(&id);
+}
+
+pub struct DownloadChannels {
+    channels: StdRwLock<HashMap<u32, Channel<DownloadEvent>>>,
+    next_id: AtomicU32,
+}
+
+impl DownloadChannels {
+    pub fn new() -> Self {
+        Self {
+            channels: StdRwLock::new(HashMap::new()),
+            next_id: AtomicU32::new(0),
+        }
+    }
+    pub fn subscribe(&self, channel: Channel<DownloadEvent>) -> u32 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.channels.write().unwrap().insert(id, channel);
+        id
+    }
+    pub fn unsubscribe(&self, id: u32) {
+        self.channels.write().unwrap().remove(&id);
+    }
+    pub fn send(&self, event: DownloadEvent) {
+        let channels = self.channels.read().unwrap().clone();
+        for (id, channel) in channels {
+            if channel.send(event.clone()).is_err() {
+                self.unsubscribe(id);
+            }
+        }
+    }
+}
+
 pub struct QueueManager {
     waiting_queue: RwLock<VecDeque<Arc<QueueInfo>>>,
     doing_queue: RwLock<VecDeque<Arc<QueueInfo>>>,
     complete_queue: RwLock<VecDeque<Arc<QueueInfo>>>,
+    failed_queue: RwLock<VecDeque<Arc<QueueInfo>>>,
+    paused_queue: RwLock<VecDeque<Arc<QueueInfo>>>,
+    persist: Mutex<()>,
+    frozen: AtomicBool,
+    restored: AtomicBool,
 }
 
 impl QueueManager {
@@ -182,52 +549,146 @@ impl QueueManager {
             waiting_queue: RwLock::new(VecDeque::new()),
             doing_queue: RwLock::new(VecDeque::new()),
             complete_queue: RwLock::new(VecDeque::new()),
+            failed_queue: RwLock::new(VecDeque::new()),
+            paused_queue: RwLock::new(VecDeque::new()),
+            persist: Mutex::new(()),
+            frozen: AtomicBool::new(false),
+            restored: AtomicBool::new(false),
         }
     }
-    pub async fn get_len(&self, queue_type: QueueType) -> usize {
+    fn queue(&self, queue_type: QueueType) -> &RwLock<VecDeque<Arc<QueueInfo>>> {
         match queue_type {
-            QueueType::Waiting => self.waiting_queue.read().await.len(),
-            QueueType::Doing => self.doing_queue.read().await.len(),
-            QueueType::Complete => self.complete_queue.read().await.len(),
+            QueueType::Waiting => &self.waiting_queue,
+            QueueType::Doing => &self.doing_queue,
+            QueueType::Complete => &self.complete_queue,
+            QueueType::Failed => &self.failed_queue,
+            QueueType::Paused => &self.paused_queue,
         }
     }
+    pub async fn get(&self, queue_type: QueueType) -> VecDeque<Arc<QueueInfo>> {
+        self.queue(queue_type).read().await.clone()
+    }
+    pub async fn get_len(&self, queue_type: QueueType) -> usize {
+        self.queue(queue_type).read().await.len()
+    }
     pub async fn update(&self, queue_type: QueueType) {
+        // Serialize snapshots so that an older one can never overwrite a newer one in storage
+        let _lock = self.persist.lock().await;
+        let data = self.get(queue_type).await;
+        // Until the stored queue is loaded, a snapshot would only hold what was added meanwhile
+        let persist = self.restored.load(Ordering::SeqCst) && !self.frozen.load(Ordering::SeqCst);
+        if queue_type != QueueType::Complete && persist {
+            if let Err(e) = queue::save(queue_type, &data).await {
+                log::error!("Failed to persist {queue_type:?} queue: {e:#}");
+            }
+        }
         match queue_type {
-            QueueType::Waiting => QueueEvent::Waiting {
-                data: self.waiting_queue.read().await.clone(),
-            },
-            QueueType::Doing => QueueEvent::Doing {
-                data: self.doing_queue.read().await.clone(),
-            },
-            QueueType::Complete => QueueEvent::Complete {
-                data: self.complete_queue.read().await.clone(),
-            },
-        }.emit(&get_app_handle()).unwrap()
+            QueueType::Waiting => QueueEvent::Waiting { data },
+            QueueType::Doing => QueueEvent::Doing { data },
+            QueueType::Complete => QueueEvent::Complete { data },
+            QueueType::Failed => QueueEvent::Failed { data },
+            QueueType::Paused => QueueEvent::Paused { data },
+        }.emit(&get_app_handle()).unwrap();
+        SCHEDULER_WAKE.notify_one();
+    }
+    pub async fn flush(&self) {
+        let _lock = self.persist.lock().await;
+        // Transitions caused by tearing down running tasks must not reach storage
+        self.frozen.store(true, Ordering::SeqCst);
+        if !self.restored.load(Ordering::SeqCst) {
+            return;
+        }
+        for queue_type in [QueueType::Waiting, QueueType::Doing, QueueType::Failed, QueueType::Paused] {
+            if let Err(e) = queue::save(queue_type, &self.get(queue_type).await).await {
+                log::error!("Failed to flush {queue_type:?} queue: {e:#}");
+            }
+        }
+    }
+    pub async fn find(&self, id: &str) -> Option<Arc<QueueInfo>> {
+        for queue_type in [QueueType::Waiting, QueueType::Doing, QueueType::Paused, QueueType::Failed] {
+            if let Some(info) = self.queue(queue_type).read().await.iter().find(|v| v.id == id) {
+                return Some(info.clone());
+            }
+        }
+        None
+    }
+    pub async fn find_task(&self, gid: &str) -> Option<Task> {
+        for queue_type in [QueueType::Waiting, QueueType::Doing, QueueType::Paused, QueueType::Failed] {
+            let guard = self.queue(queue_type).read().await;
+            let task = guard.iter().flat_map(|v| v.tasks.iter()).find(|v| v.gid.as_deref() == Some(gid));
+            if let Some(task) = task {
+                return Some(task.clone());
+            }
+        }
+        None
+    }
+    pub async fn modify(&self, id: &str, f: impl FnOnce(&mut QueueInfo)) -> Option<(QueueType, Arc<QueueInfo>)> {
+        for queue_type in [QueueType::Waiting, QueueType::Doing, QueueType::Paused, QueueType::Failed] {
+            let mut guard = self.queue(queue_type).write().await;
+            if let Some(entry) = guard.iter_mut().find(|v| v.id == id) {
+                let mut info = (**entry).clone();
+                f(&mut info);
+                *entry = Arc::new(info);
+                let info = entry.clone();
+                drop(guard);
+                self.update(queue_type).await;
+                return Some((queue_type, info));
+            }
+        }
+        None
+    }
+    pub async fn transfer(
+        &self,
+        id: &str,
+        from: QueueType,
+        to: QueueType,
+        f: impl FnOnce(&mut QueueInfo),
+    ) -> Option<Arc<QueueInfo>> {
+        let mut guard = self.queue(from).write().await;
+        let index = guard.iter().position(|v| v.id == id)?;
+        let mut info = (*guard.remove(index)?).clone();
+        drop(guard);
+        f(&mut info);
+        let info = Arc::new(info);
+        self.queue(to).write().await.push_back(info.clone());
+        self.update(from).await;
+        self.update(to).await;
+        Some(info)
     }
     pub async fn push_back(&self, info: Arc<QueueInfo>, queue_type: QueueType) -> Result<()> {
-        let mut guard = match queue_type {
-            QueueType::Waiting => self.waiting_queue.write().await,
-            QueueType::Doing => self.doing_queue.write().await,
-            QueueType::Complete => self.complete_queue.write().await,
-        };
+        let mut guard = self.queue(queue_type).write().await;
         guard.push_back(info);
         drop(guard);
         Ok(())
     }
     pub async fn retain(&self, queue_type: QueueType, id: String) -> Result<()> {
-        let mut guard = match queue_type {
-            QueueType::Waiting => self.waiting_queue.write().await,
-            QueueType::Doing => self.doing_queue.write().await,
-            QueueType::Complete => self.complete_queue.write().await,
-        };
+        let mut guard = self.queue(queue_type).write().await;
         guard.retain(|task| *task.id != id);
         drop(guard);
         self.update(queue_type).await;
         Ok(())
     }
+    pub async fn move_to(&self, id: &str, position: QueuePosition) -> Result<()> {
+        let mut guard = self.waiting_queue.write().await;
+        let index = guard.iter().position(|v| v.id == id)
+            .ok_or(anyhow!("Waiting item {id} not found"))?;
+        let info = guard.remove(index).unwrap();
+        let target = match position {
+            QueuePosition::Top => 0,
+            QueuePosition::Bottom => guard.len(),
+            QueuePosition::Index(i) => i.min(guard.len()),
+        };
+        guard.insert(target, info);
+        drop(guard);
+        self.update(QueueType::Waiting).await;
+        Ok(())
+    }
     async fn waiting_to_doing(&self) -> Result<Option<Arc<QueueInfo>>> {
         let mut waiting_queue = self.waiting_queue.write().await;
-        if let Some(info) = waiting_queue.pop_front() {
+        // Highest priority first, queue order breaks ties
+        let next = waiting_queue.iter().map(|v| v.priority).max()
+            .and_then(|max| waiting_queue.iter().position(|v| v.priority == max));
+        if let Some(info) = next.and_then(|i| waiting_queue.remove(i)) {
             drop(waiting_queue);
             self.push_back(info.clone(), QueueType::Doing).await?;
             self.update(QueueType::Waiting).await;
@@ -243,15 +704,87 @@ impl QueueManager {
         self.update(QueueType::Complete).await;
     Ok(())
     }
+    async fn doing_to_failed(&self, info: Arc<QueueInfo>, task_type: TaskType, err: &TauriError) -> Result<()> {
+        let mut failed = (*info).clone();
+        failed.error = Some(QueueError {
+            code: err.code,
+            message: err.message.clone(),
+            task_type,
+        });
+        self.retain(QueueType::Doing, info.id.clone()).await?;
+        self.push_back(Arc::new(failed), QueueType::Failed).await?;
+        self.update(QueueType::Failed).await;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct CancelToken {
+    cancelled: AtomicBool,
+    committed: AtomicBool,
+    notify: Notify,
+    children: StdMutex<Vec<u32>>,
+}
+
+impl CancelToken {
+    fn register(id: &str) -> Arc<Self> {
+        let token = Arc::new(Self::default());
+        CANCEL_TOKENS.write().unwrap().insert(id.into(), token.clone());
+        token
+    }
+    // Returns false once the work has been committed and can no longer be called off
+    fn cancel(&self) -> bool {
+        let mut children = self.children.lock().unwrap();
+        if self.committed.load(Ordering::SeqCst) {
+            return false;
+        }
+        self.cancelled.store(true, Ordering::SeqCst);
+        // Children are killed directly, the futures waiting on them are simply dropped
+        for pid in children.drain(..) {
+            kill_child(pid);
+        }
+        drop(children);
+        self.notify.notify_waiters();
+        true
+    }
+    // Either this or cancel wins, both are decided under the children lock
+    fn commit(&self) -> bool {
+        let _children = self.children.lock().unwrap();
+        if self.is_cancelled() {
+            return false;
+        }
+        self.committed.store(true, Ordering::SeqCst);
+        true
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+    pub fn attach(&self, pid: u32) {
+        let mut children = self.children.lock().unwrap();
+        if self.is_cancelled() {
+            kill_child(pid);
+        } else {
+            children.push(pid);
+        }
+    }
 }
 
 struct DownloadManager {
-    event: Channel<DownloadEvent>,
+    event: &'static DownloadChannels,
 }
 
 impl DownloadManager {
-    pub fn new(event: Channel<DownloadEvent>) -> Self {
-        Self { event }
+    pub fn new() -> Self {
+        Self { event: &DOWNLOAD_EVENTS }
     }
     pub async fn process_tasks(
         self: Arc<Self>, 
@@ -259,24 +792,54 @@ impl DownloadManager {
     ) -> Result<()> {
         let max_conc = CONFIG.read().unwrap().max_conc;
         let doing_len = QUEUE_MANAGER.get_len(QueueType::Doing).await;
-        for _ in 0..(max_conc - doing_len) {
+        // max_conc may be lowered while tasks are running
+        for _ in 0..max_conc.saturating_sub(doing_len) {
             if let Some(info) = QUEUE_MANAGER.waiting_to_doing().await? {
                 let self_cloned = self.clone();
                 let tx_cloned = tx.clone();
                 async_runtime::spawn(async move {
+                    let token = CancelToken::register(&info.id);
+                    let processed = tokio::select! {
+                        result = self_cloned.process(info.clone(), &token) => Some(result),
+                        _ = token.cancelled() => None,
+                    };
+                    // From here on the item is recorded as it is, remove_task has to wait for that
+                    let processed = processed.filter(|_| token.commit());
+                    // remove_task owns the queue entry and the files of a cancelled item
+                    let Some(processed) = processed else {
+                        CANCEL_TOKENS.write().unwrap().remove(&info.id);
+                        ITEM_PROGRESS.write().unwrap().remove(&info.id);
+                        ACTIVE_GIDS.write().unwrap().retain(|_, (id, _)| **id != info.id);
+                        return Ok(());
+                    };
+                    let id = info.id.clone();
                     let result = async {
-                        let success = self_cloned.process(info.clone()).await?;
+                        let verified = match processed {
+                            Ok(verified) => verified,
+                            Err((task_type, e)) => {
+                                QUEUE_MANAGER.doing_to_failed(info.clone(), task_type, &e).await?;
+                                return Err(e);
+                            }
+                        };
                         let path = info.tasks[0].clone().path.unwrap();
-                        if success {
-                            downloads::insert(info.clone()).await?;
-                            QUEUE_MANAGER.doing_to_complete(info.clone()).await?;                    
-                            if info.tasks.len() < 2 {
-                                fs::rename(&path, &info.output)?;
+                        // Files on an unmapped external aria2 stay where it put them
+                        let local = is_local(&path);
+                        if local {
+                            let moved = (info.tasks.len() < 2).then_some(path.clone());
+                            if let Err(e) = finalize(verified.clone(), moved).await {
+                                let task_type = info.tasks.last().unwrap().task_type.clone();
+                                QUEUE_MANAGER.doing_to_failed(info.clone(), task_type, &e).await?;
+                                return Err(e);
                             }
                         }
-                        fs::remove_dir_all(&path.parent().unwrap())?;
+                        downloads::insert(verified.clone()).await?;
+                        QUEUE_MANAGER.doing_to_complete(verified).await?;
+                        if local {
+                            fs::remove_dir_all(&path.parent().unwrap())?;
+                        }
                         Ok::<Arc<QueueInfo>, TauriError>(info)
                     }.await;
+                    CANCEL_TOKENS.write().unwrap().remove(&id);
                     tx_cloned.send(result).await.context("Failed to send task result")?;
                     Ok::<(), TauriError>(())
                 });
@@ -286,98 +849,325 @@ impl DownloadManager {
         }
         Ok(())
     }
-    async fn process(&self, info: Arc<QueueInfo>) -> TauriResult<bool> {
+    async fn process(&self, info: Arc<QueueInfo>, token: &CancelToken) -> Result<Arc<QueueInfo>, (TaskType, TauriError)> {
         let id = Arc::new(info.id.clone());
-        for task in info.tasks.iter() {
-            if task.task_type == TaskType::Merge {
-                ffmpeg::merge(info.clone(), &self.event).await?;
-                continue;
+        ITEM_PROGRESS.write().unwrap().insert(info.id.clone(), ItemProgress {
+            stage: None,
+            tasks: info.tasks.iter().filter_map(|v|
+                Some((v.gid.clone()?, TaskProgress::new(v.task_type.clone())))
+            ).collect(),
+        });
+        let result = async {
+            for task in info.tasks.iter() {
+                if let Some(progress) = ITEM_PROGRESS.write().unwrap().get_mut(&info.id) {
+                    progress.stage = Some(task.task_type.clone());
+                }
+                self.process_task(info.clone(), task, token).await
+                    .map_err(|e| (task.task_type.clone(), e))?;
+                self.finish_task(&id, task);
             }
-            if task.task_type == TaskType::Flac {
-                ffmpeg::raw_flac(info.clone()).await?;
-                continue;
+            let verification = self.verify(&info, token).await
+                .map_err(|e| (info.tasks.last().unwrap().task_type.clone(), e))?;
+            Ok(Arc::new(QueueInfo { verification, ..(*info).clone() }))
+        }.await;
+        ITEM_PROGRESS.write().unwrap().remove(&info.id);
+        result
+    }
+    async fn verify(&self, info: &QueueInfo, token: &CancelToken) -> TauriResult<Option<Verification>> {
+        let mode = CONFIG.read().unwrap().verify;
+        let paths: Vec<_> = info.tasks.iter().filter_map(|v| v.path.as_deref()).collect();
+        if mode == VerifyMode::Off || !paths.iter().all(|v| is_local(v)) {
+            return Ok(None);
+        }
+        self.event.send(DownloadEvent::Verifying { id: Arc::new(info.id.clone()) });
+        let mut size = 0;
+        let mut expected = Some(0);
+        for task in info.tasks.iter().filter(|v| v.urls.is_some()) {
+            let Some(path) = &task.path else { continue };
+            let actual = fs::metadata(path)?.len();
+            if let Some(bytes) = task.size.filter(|v| *v != actual) {
+                return Err(anyhow!(
+                    "Size mismatch for {}: {actual} bytes, expected {bytes}", path.display()
+                ).into());
+            }
+            size += actual;
+            expected = expected.zip(task.size).map(|(a, b)| a + b);
+        }
+        let decoded = mode == VerifyMode::Decode;
+        if decoded {
+            // Processed items are checked in their final form, a lone stream as downloaded
+            let processed = info.tasks.iter().any(|v| matches!(v.task_type, TaskType::Merge | TaskType::Flac));
+            let target = if processed {
+                info.output.as_path()
+            } else {
+                paths.first().copied().ok_or(anyhow!("Nothing was downloaded for {}", info.id))?
+            };
+            ffmpeg::decode_check(target, token).await?;
+        }
+        Ok(Some(Verification { size, expected, decoded }))
+    }
+    fn finish_task(&self, id: &Arc<String>, task: &Task) {
+        let Some(gid) = &task.gid else { return };
+        if let Some(progress) = ITEM_PROGRESS.write().unwrap()
+            .get_mut(&**id).and_then(|v| v.tasks.get_mut(gid))
+        {
+            progress.finished = true;
+            progress.speed = 0;
+        }
+        send_aggregate(id);
+    }
+    async fn process_task(&self, info: Arc<QueueInfo>, task: &Task, token: &CancelToken) -> TauriResult<()> {
+        let id = Arc::new(info.id.clone());
+        // Hold the next task back while the whole item is paused
+        while QUEUE_MANAGER.find(&id).await.is_some_and(|v| v.paused) {
+            sleep(Duration::from_millis(500)).await;
+        }
+        if matches!(task.task_type, TaskType::Merge | TaskType::Flac)
+            && info.tasks.iter().filter_map(|v| v.path.as_deref()).any(|v| !is_local(v))
+        {
+            return Err(anyhow!(
+                "Files downloaded by the external aria2 are not reachable locally, set a local directory mapping to process them"
+            ).into());
+        }
+        if task.task_type == TaskType::Merge {
+            ffmpeg::merge(info.clone(), self.event, token).await?;
+            return Ok(());
+        }
+        if task.task_type == TaskType::Flac {
+            ffmpeg::raw_flac(info.clone(), token).await?;
+            return Ok(());
+        }
+        let gid = Arc::new(task.gid.as_ref().unwrap().clone());
+        let finished = match get_status(task, &gid).await? {
+            Some(status) => status.state == DownloadState::Complete,
+            None => task.path.as_deref().is_some_and(is_downloaded),
+        };
+        if finished {
+            self.event.send(DownloadEvent::Finished { id: id.clone(), gid: gid.clone() });
+            return Ok(());
+        }
+        // Subscribe before unpausing so that no notification for this gid can be missed
+        let mut events = downloader::subscribe();
+        task.backend.resume(&gid).await?;
+        self.event.send(DownloadEvent::Started {
+            id: id.clone(), gid: gid.clone(), task_type: task.task_type.clone()
+        });
+        ACTIVE_GIDS.write().unwrap().insert((*gid).clone(), (id.clone(), task.backend));
+        let result = self.wait_task(id, gid.clone(), task, &mut events).await;
+        ACTIVE_GIDS.write().unwrap().remove(&*gid);
+        result
+    }
+    async fn wait_task(
+        &self,
+        id: Arc<String>,
+        gid: Arc<String>,
+        task: &Task,
+        events: &mut broadcast::Receiver<StateChange>,
+    ) -> TauriResult<()> {
+        let mut attempt = 0;
+        loop {
+            // Status is only queried on a relevant notification, or as a fallback
+            // when the WebSocket connection stays silent for a while
+            match timeout(Duration::from_secs(3), events.recv()).await {
+                Ok(Ok(event)) if event.gid != *gid => continue,
+                Ok(Ok(event)) if matches!(event.state, DownloadState::Active | DownloadState::Paused) => continue,
+                _ => (),
             }
-            let gid = Arc::new(task.gid.as_ref().unwrap().clone());
-            let body: Aria2General = serde_json::from_value(
-                post_aria2c("unpause", vec![json!(gid)]).await?
-            ).context("Failed to decode aria2c unpause response")?;
-            if let Some(err) = body.error {
-                QUEUE_MANAGER.retain(QueueType::Doing, (*id).clone()).await?;
-                return Err(TauriError::new(err.message, Some(err.code)));
-            }
-            self.event.send(DownloadEvent::Started {
-                id: id.clone(), gid: gid.clone(), task_type: task.task_type.clone()
-            })?;
-            let mut success = false;
-            loop {
-                let body: Aria2TellStatus = serde_json::from_value(
-                    post_aria2c("tellStatus", vec![json!(gid)]).await?
-                ).context("Failed to decode aria2c tellStatus response")?;
-                if let Some(error) = body.error {
-                    if error.code == 1 {
-                        break;
+            // A gid removed outside of the app would otherwise hold its slot forever,
+            // unless its file was completed before the result was dropped
+            let Some(data) = get_status(task, &gid).await? else {
+                if task.path.as_deref().is_some_and(is_downloaded) {
+                    self.event.send(DownloadEvent::Finished { id: id.clone(), gid: gid.clone() });
+                    return Ok(());
+                }
+                return Err(anyhow!("Download {gid} is no longer known to the download backend").into());
+            };
+            if let Some(code) = data.error_code {
+                if code != 0 && code != 31 {
+                    let message = data.error_message.unwrap_or_default();
+                    let max_retry = CONFIG.read().unwrap().max_retry;
+                    if attempt < max_retry {
+                        attempt += 1;
+                        self.event.send(DownloadEvent::Retrying {
+                            id: id.clone(), gid: gid.clone(),
+                            attempt, max_attempts: max_retry, code, message,
+                        });
+                        let limit = QUEUE_MANAGER.find(&id).await.and_then(|v| v.speed_limit);
+                        retry(task, &gid, attempt, limit).await?;
+                        continue;
                     }
-                    return Err(TauriError::new(error.message, Some(error.code)));
+                    return Err(TauriError::new(message, Some(code)));
                 }
-                if let Some(data) = body.result {
-                    if let Some(code) = data.error_code {
-                        let code = code.parse::<isize>()?;
-                        if code != 0 && code != 31 {
-                            QUEUE_MANAGER.retain(QueueType::Doing, (*id).clone()).await?;
-                            return Err(TauriError::new(
-                                data.error_message.unwrap_or(String::new()), Some(code)
-                            ));
-                        }
+            }
+            match data.state {
+                DownloadState::Complete => {
+                    self.event.send(DownloadEvent::Finished { id: id.clone(), gid: gid.clone() });
+                    return Ok(());
+                },
+                DownloadState::Active | DownloadState::Waiting | DownloadState::Paused => (),
+                state => return Err(anyhow!("Download {gid} stopped with state {state:?}").into()),
+            }
+        }
+    }
+}
+
+async fn finalize(info: Arc<QueueInfo>, moved: Option<PathBuf>) -> TauriResult<()> {
+    let id = Arc::new(info.id.clone());
+    async_runtime::spawn_blocking(move || -> Result<()> {
+        let output = &info.output;
+        let expected = match &moved {
+            Some(source) => {
+                let expected = fs::metadata(source)?.len();
+                if let Err(e) = fs::rename(source, output) {
+                    // Most likely a different filesystem, which rename can't cross
+                    log::info!("Rename to {} failed, copying instead: {e}", output.display());
+                    let part = output.with_file_name(format!(
+                        "{}.part", output.file_name().unwrap().to_string_lossy()
+                    ));
+                    if let Err(e) = copy_part(&id, source, &part, expected) {
+                        let _ = fs::remove_file(&part);
+                        return Err(e);
                     }
-                    match data.status.as_str() {
-                        "active" => {
-                            self.event.send(DownloadEvent::Progress {
-                                id: id.clone(), gid: gid.clone(),
-                                content_length: data.total_length.parse::<u64>()?,
-                                chunk_length: data.completed_length.parse::<u64>()?,
-                            })?;
-                        },
-                        "complete" => {
-                            self.event.send(DownloadEvent::Finished { id: id.clone(), gid: gid.clone() })?;
-                            success = true;
-                            break;
-                        },
-                        "paused" => (),
-                        _ => break,
+                    fs::rename(&part, output)?;
+                    fs::remove_file(source)?;
+                }
+                Some(expected)
+            },
+            None => None,
+        };
+        let size = fs::metadata(output).map(|v| v.len()).unwrap_or(0);
+        if size == 0 || expected.is_some_and(|v| v != size) {
+            return Err(anyhow!(
+                "Output {} is incomplete: {size} bytes, expected {}",
+                output.display(), expected.map_or("more than 0".into(), |v| v.to_string())
+            ));
+        }
+        Ok(())
+    }).await.map_err(anyhow::Error::from)??;
+    Ok(())
+}
+
+fn copy_part(id: &Arc<String>, source: &Path, part: &Path, total: u64) -> Result<()> {
+    use std::io::{Read, Write};
+    let mut reader = fs::File::open(source)?;
+    let mut writer = fs::File::create(part)?;
+    let mut buf = vec![0; 1024 * 1024];
+    let (mut copied, mut last) = (0, Instant::now());
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        writer.write_all(&buf[..len])?;
+        copied += len as u64;
+        if last.elapsed() >= Duration::from_millis(500) {
+            DOWNLOAD_EVENTS.send(DownloadEvent::Finalizing {
+                id: id.clone(), content_length: total, chunk_length: copied
+            });
+            last = Instant::now();
+        }
+    }
+    // Only a synced copy may replace the source
+    writer.sync_all()?;
+    DOWNLOAD_EVENTS.send(DownloadEvent::Finalizing {
+        id: id.clone(), content_length: total, chunk_length: copied
+    });
+    Ok(())
+}
+
+async fn listen() {
+    let mut attempt = 0u32;
+    loop {
+        // http(s)://host/jsonrpc -> ws(s)://host/jsonrpc
+        let url = rpc_endpoint().0.replacen("http", "ws", 1);
+        if url.starts_with("wss") {
+            // No TLS support is built in, wait_task falls back to polling the status
+            sleep(Duration::from_secs(10)).await;
+            continue;
+        }
+        match connect_async(url.as_str()).await {
+            Ok((mut stream, _)) => {
+                attempt = 0;
+                while let Some(Ok(message)) = stream.next().await {
+                    let Message::Text(text) = message else { continue };
+                    let Ok(notification) = serde_json::from_str::<Aria2Notification>(text.as_str()) else {
+                        continue;
+                    };
+                    let state = match notification.method.trim_start_matches("aria2.") {
+                        "onDownloadStart" => DownloadState::Active,
+                        "onDownloadPause" => DownloadState::Paused,
+                        "onDownloadStop" => DownloadState::Removed,
+                        "onDownloadComplete" | "onBtDownloadComplete" => DownloadState::Complete,
+                        "onDownloadError" => DownloadState::Error,
+                        _ => continue,
+                    };
+                    for params in notification.params {
+                        downloader::notify(&params.gid, state);
                     }
                 }
-                sleep(Duration::from_millis(100)).await;
+            },
+            Err(e) => {
+                // Only the first failure of a streak is logged
+                if attempt == 0 {
+                    log::warn!("Failed to connect to aria2c WebSocket: {e}");
+                }
+                attempt += 1;
+            },
+        }
+        // Exponential backoff, capped at 64 seconds
+        sleep(Duration::from_millis(500 << attempt.min(7))).await;
+    }
+}
+
+async fn progress_ticker() {
+    loop {
+        sleep(Duration::from_millis(500)).await;
+        let active = ACTIVE_GIDS.read().unwrap().clone();
+        if active.is_empty() {
+            continue;
+        }
+        let mut statuses = vec![];
+        for backend in [DownloadBackend::Aria2, DownloadBackend::Native] {
+            if !active.values().any(|(_, v)| *v == backend) {
+                continue;
             }
-            if !success {
-                return Ok(false);
+            match backend.active().await {
+                Ok(result) => statuses.extend(result),
+                Err(e) => log::warn!("Failed to query active downloads of {backend:?}: {e}"),
             }
         }
-        Ok(true)
+        for status in statuses {
+            let Some((id, _)) = active.get(&status.gid) else { continue };
+            send_progress(
+                id.clone(), Arc::new(status.gid),
+                status.total_length,
+                status.completed_length,
+                status.download_speed,
+                status.connections,
+            );
+        }
+    }
+}
+
+async fn retry(task: &Task, gid: &str, attempt: usize, limit: Option<u64>) -> TauriResult<()> {
+    // Exponential backoff, capped at 64 seconds
+    sleep(Duration::from_secs(1u64 << (attempt - 1).min(6))).await;
+    let mut urls = task.urls.clone().unwrap_or_default();
+    if urls.is_empty() {
+        return Err(anyhow!("No URLs to retry for {gid}").into());
     }
+    // Rotate through backup URLs so each attempt starts from a different mirror
+    let len = urls.len();
+    urls.rotate_left(attempt % len);
+    let path = task.path.as_deref().ok_or(anyhow!("No output path for {gid}"))?;
+    task.backend.forget(gid).await?;
+    task.backend.add(&urls, path, Some(gid), false, limit).await?;
+    Ok(())
 }
 
-async fn daemon(name: String, child: &CommandChild, rx: &mut Receiver<CommandEvent>) {
+async fn daemon(name: String, pid: u32, rx: &mut Receiver<CommandEvent>) -> String {
     use process_alive::{State, Pid};
     let app = get_app_handle();
-    let pid = child.pid();
-    #[cfg(target_os = "windows")]
-    let cmd = ("C:\\Windows\\System32\\WindowsPowerShell\\v1.0\\powershell.exe", [
-        "-Command",
-        &format!(
-            "while ((Get-Process -Id {} -ErrorAction SilentlyContinue) -ne $null) \
-            {{ Start-Sleep -Milliseconds 500 }}; Stop-Process -Id {} -Force",
-            std::process::id(), pid
-        )
-    ]);
-    #[cfg(any(target_os = "macos", target_os = "linux"))]
-    let cmd = ("/bin/bash", [
-        "-c",
-        &format!(
-            "while kill -0 {} 2>/dev/null; do sleep 0.5; done; kill {}",
-            std::process::id(), pid
-        )
-    ]);
-    let _ = app.shell().command(cmd.0).args(cmd.1).spawn();
     let stderr = Arc::new(tokio::sync::RwLock::new(String::new()));
     let stderr_clone = stderr.clone();
     async_runtime::spawn(async move {
@@ -396,11 +1186,9 @@ async fn daemon(name: String, child: &CommandChild, rx: &mut Receiver<CommandEve
                 }.emit(&app).unwrap();
                 lock.clear();
             }
-            let pid = Pid::from(pid);
-            if process_alive::state(pid) != State::Alive {
-                break SidecarError {
-                    name: name.clone(), error: format!("Process {name} ({pid}) is dead")
-                }.emit(&app).unwrap();
+            // Exits are reported by the supervisor
+            if process_alive::state(Pid::from(pid)) != State::Alive {
+                break;
             }
             if let Err(e) = post_aria2c("getGlobalStat", vec![]).await {
                 SidecarError {
@@ -409,19 +1197,265 @@ async fn daemon(name: String, child: &CommandChild, rx: &mut Receiver<CommandEve
             }
         }
     });
+    let mut reason = format!("Process aria2c ({pid}) is dead");
     while let Some(event) = rx.recv().await {
-        if let CommandEvent::Stderr(line) = event {
-            let line = String::from_utf8_lossy(&line);
-            if !line.trim().is_empty() {
-                let mut lock = stderr.write().await;
-                lock.push_str(&line.to_string());
-            }
+        match event {
+            CommandEvent::Stderr(line) => {
+                let line = String::from_utf8_lossy(&line);
+                if !line.trim().is_empty() {
+                    let mut lock = stderr.write().await;
+                    lock.push_str(&line.to_string());
+                }
+            },
+            CommandEvent::Terminated(payload) => {
+                reason = format!(
+                    "Process aria2c ({pid}) exited with code {:?}, signal {:?}",
+                    payload.code, payload.signal
+                );
+            },
+            _ => (),
         }
     }
+    reason
 }
 
-pub fn init() -> Result<()> {
-    let port = (6800..65535)
+async fn supervise(mut rx: Receiver<CommandEvent>, mut pid: u32) {
+    // A sidecar that survives this long is considered stable again
+    const STABLE: Duration = Duration::from_secs(60);
+    let mut attempt = 0;
+    loop {
+        let started = Instant::now();
+        let mut reason = daemon("aria2c".into(), pid, &mut rx).await;
+        untrack_child(pid);
+        if *SHUTTING_DOWN.read().unwrap() {
+            ARIA2C_RUNNING.store(false, Ordering::SeqCst);
+            break;
+        }
+        ARIA2C_RESTARTING.store(true, Ordering::SeqCst);
+        log::warn!("{reason}");
+        if started.elapsed() >= STABLE {
+            attempt = 0;
+        }
+        loop {
+            attempt += 1;
+            // Exponential backoff against crash loops, capped at 64 seconds
+            let delay = 1u64 << (attempt - 1).min(6);
+            Aria2Health::Restarting { attempt, delay, reason: reason.clone() }
+                .emit(&get_app_handle()).unwrap();
+            sleep(Duration::from_secs(delay)).await;
+            match spawn_aria2c() {
+                Ok(spawned) => {
+                    (rx, pid) = spawned;
+                    break;
+                },
+                Err(e) => reason = format!("{e:#}"),
+            }
+        }
+        if let Err(e) = recover().await {
+            log::error!("Failed to recover aria2c downloads: {e}");
+        }
+        ARIA2C_RESTARTING.store(false, Ordering::SeqCst);
+        Aria2Health::Healthy { port: *ARIA2C_PORT.read().unwrap() }
+            .emit(&get_app_handle()).unwrap();
+    }
+}
+
+async fn recover() -> TauriResult<()> {
+    wait_rpc().await?;
+    // The session file may be up to 10 seconds old, so every known gid is checked again
+    for queue_type in [QueueType::Doing, QueueType::Waiting, QueueType::Paused] {
+        for info in QUEUE_MANAGER.get(queue_type).await.iter() {
+            if let Err(e) = reconcile(info, &[DownloadBackend::Aria2]).await {
+                log::warn!("Failed to re-register queue item {} with aria2c: {}", info.id, e);
+            }
+        }
+    }
+    // reconcile leaves everything paused, resume the gids that doing items are waiting on
+    let active = ACTIVE_GIDS.read().unwrap().clone();
+    for (gid, (id, backend)) in active {
+        if backend != DownloadBackend::Aria2 || QUEUE_MANAGER.find(&id).await.is_none_or(|v| v.paused) {
+            continue;
+        }
+        if let Err(e) = call_aria2c("unpause", vec![json!(gid)]).await {
+            log::warn!("Failed to resume {gid}: {e}");
+        }
+    }
+    Ok(())
+}
+
+async fn wait_rpc() -> TauriResult<()> {
+    let mut retries = 0;
+    loop {
+        let Err(e) = post_aria2c("getVersion", vec![]).await else { return Ok(()) };
+        if retries >= 40 {
+            return Err(anyhow!("aria2c JsonRPC is not responding: {}", e.message).into());
+        }
+        retries += 1;
+        sleep(Duration::from_millis(250)).await;
+    }
+}
+
+fn current_speed_limit() -> u64 {
+    let config = CONFIG.read().unwrap();
+    let now = chrono::Local::now().time();
+    config.speed_limit.schedule.iter()
+        .find(|rule| rule.contains(now))
+        .map(|rule| rule.limit)
+        .unwrap_or(config.speed_limit.global)
+}
+
+async fn limiter() {
+    let mut applied = None;
+    loop {
+        let limit = current_speed_limit();
+        native::set_global_limit(limit);
+        // Like the tuning options, the limit of a shared external daemon is left to whoever runs it
+        if applied != Some(limit) && ARIA2C_RUNNING.load(Ordering::SeqCst) && !is_external() {
+            let options = json!({ "max-overall-download-limit": limit.to_string() });
+            match call_aria2c("changeGlobalOption", vec![options]).await {
+                Ok(_) => {
+                    log::info!("Global download speed limit set to {limit} B/s");
+                    applied = Some(limit);
+                },
+                Err(e) => log::warn!("Failed to apply global download speed limit: {e}"),
+            }
+        }
+        sleep(Duration::from_secs(5)).await;
+    }
+}
+
+pub async fn shutdown() {
+    SCHEDULER_ACTIVE.store(false, Ordering::SeqCst);
+    // Give running merges a chance to finish so that their items are recorded as complete
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while has_child("ffmpeg") && Instant::now() < deadline {
+        sleep(Duration::from_millis(250)).await;
+    }
+    QUEUE_MANAGER.flush().await;
+    native::shutdown().await;
+    if !ARIA2C_RUNNING.load(Ordering::SeqCst) || is_external() {
+        return;
+    }
+    for action in ["saveSession", "shutdown"] {
+        if let Err(e) = call_aria2c(action, vec![]).await {
+            log::warn!("Failed to {action} aria2c: {e}");
+        }
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while has_child("aria2c") && Instant::now() < deadline {
+        sleep(Duration::from_millis(250)).await;
+    }
+}
+
+pub async fn apply_options() -> TauriResult<()> {
+    // A shared external daemon is tuned by whoever runs it
+    if !ARIA2C_RUNNING.load(Ordering::SeqCst) || is_external() {
+        return Ok(());
+    }
+    let options: serde_json::Map<String, Value> = CONFIG.read().unwrap().aria2.options(false)
+        .into_iter().map(|(k, v)| (k.to_string(), json!(v))).collect();
+    call_aria2c("changeGlobalOption", vec![Value::Object(options)]).await?;
+    Ok(())
+}
+
+pub async fn restore() -> Result<()> {
+    let mut reachable = true;
+    if ARIA2C_RUNNING.load(Ordering::SeqCst) {
+        if let Err(e) = wait_rpc().await {
+            if is_external() {
+                // The remote daemon may come back later, queue items are retried against it then
+                log::warn!("External aria2 is not responding: {}", e.message);
+                reachable = false;
+            } else if CONFIG.read().unwrap().download_backend == DownloadBackend::Native {
+                log::warn!("aria2c is not responding, continuing with the native backend: {}", e.message);
+                ARIA2C_RUNNING.store(false, Ordering::SeqCst);
+            } else {
+                // Items are restored anyway and checked again when they start or are retried
+                log::error!("aria2c is not responding, queue items can't be reconciled: {}", e.message);
+                reachable = false;
+            }
+        }
+    }
+    let mut items = queue::load().await?;
+    // Interrupted items go first so that they resume before untouched ones
+    items.sort_by_key(|(queue_type, _)| *queue_type != QueueType::Doing);
+    for (queue_type, info) in items {
+        if queue_type == QueueType::Failed {
+            QUEUE_MANAGER.push_back(Arc::new(info), QueueType::Failed).await?;
+            continue;
+        }
+        // Every call would wait out the RPC timeout on its own
+        let backends: &[_] = if reachable {
+            &[DownloadBackend::Aria2, DownloadBackend::Native]
+        } else {
+            &[DownloadBackend::Native]
+        };
+        if let Err(e) = reconcile(&info, backends).await {
+            log::warn!("Failed to reconcile queue item {} with its download backend: {}", info.id, e);
+        }
+        let queue_type = if info.paused { QueueType::Paused } else { QueueType::Waiting };
+        QUEUE_MANAGER.push_back(Arc::new(info), queue_type).await?;
+    }
+    QUEUE_MANAGER.restored.store(true, Ordering::SeqCst);
+    QUEUE_MANAGER.update(QueueType::Waiting).await;
+    QUEUE_MANAGER.update(QueueType::Doing).await;
+    QUEUE_MANAGER.update(QueueType::Failed).await;
+    QUEUE_MANAGER.update(QueueType::Paused).await;
+    Ok(())
+}
+
+async fn reconcile(info: &QueueInfo, backends: &[DownloadBackend]) -> TauriResult<()> {
+    for task in info.tasks.iter().filter(|v| backends.contains(&v.backend)) {
+        let (Some(urls), Some(gid), Some(path)) = (&task.urls, &task.gid, &task.path) else {
+            continue;
+        };
+        let backend = task.backend;
+        match backend.status(gid).await? {
+            Some(status) => match status.state {
+                DownloadState::Active | DownloadState::Waiting => backend.pause(gid).await?,
+                DownloadState::Paused | DownloadState::Complete => (),
+                DownloadState::Error | DownloadState::Removed => {
+                    backend.forget(gid).await?;
+                    backend.add(urls, path, Some(gid.as_str()), true, info.speed_limit).await?;
+                }
+            },
+            None => if !is_downloaded(path) {
+                backend.add(urls, path, Some(gid.as_str()), true, info.speed_limit).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn init() -> Result<()> {
+    async_runtime::spawn(progress_ticker());
+    async_runtime::spawn(limiter());
+    // The sidecar is set up once, so the mode it was set up for holds until the next launch
+    ARIA2C_EXTERNAL.store(CONFIG.read().unwrap().aria2_rpc.external, Ordering::SeqCst);
+    if is_external() {
+        ARIA2C_RUNNING.store(true, Ordering::SeqCst);
+        async_runtime::spawn(listen());
+        return Ok(());
+    }
+    match spawn_aria2c() {
+        Ok((rx, pid)) => {
+            async_runtime::spawn(supervise(rx, pid));
+            async_runtime::spawn(listen());
+        },
+        // The native backend works without the sidecar, so a missing aria2c is not fatal there
+        Err(e) => if CONFIG.read().unwrap().download_backend == DownloadBackend::Aria2 {
+            return Err(e);
+        } else {
+            log::warn!("Failed to start aria2c, only the native backend is available: {e:#}");
+        }
+    }
+    Ok(())
+}
+
+fn spawn_aria2c() -> Result<(Receiver<CommandEvent>, u32)> {
+    // Prefer the previous port so that a restarted sidecar keeps its address
+    let previous = *ARIA2C_PORT.read().unwrap();
+    let port = iter::once(previous).filter(|p| *p != 0).chain(6800..65535)
         .find_map(|p| TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], p))).ok())
         .ok_or(anyhow!("No free port found"))?.local_addr()?.port();
     *ARIA2C_PORT.write().unwrap() = port;
@@ -431,7 +1465,9 @@ pub fn init() -> Result<()> {
     let session_file = session_file.to_string_lossy();
     let log_file = app.path().app_log_dir()?.join("aria2.log");
     let log_file = log_file.to_string_lossy();
-    let (mut rx, child) = app.shell().sidecar("aria2c").map_err(|e| process_err(e, "aria2c"))?
+    let tuning = CONFIG.read().unwrap().aria2.options(true)
+        .into_iter().map(|(k, v)| format!("--{k}={v}"));
+    let (rx, child) = app.shell().sidecar("aria2c").map_err(|e| process_err(e, "aria2c"))?
     .args([
         "--enable-rpc".into(),
         "--log-level=warn".into(),
@@ -439,20 +1475,68 @@ pub fn init() -> Result<()> {
         "--header=Origin: https://www.bilibili.com".into(),
         format!("--input-file={session_file}"),
         format!("--save-session={session_file}"),
+        "--save-session-interval=10".into(),
         format!("--user-agent={USER_AGENT}"),
         format!("--rpc-listen-port={port}"),
         format!("--rpc-secret={}", &SECRET.read().unwrap()),
         format!("--log={log_file}"),
-    ]).spawn().map_err(|e| process_err(e, "aria2c"))?;
-    async_runtime::spawn(async move {
-        daemon("aria2c".into(), &child, &mut rx).await;
-    });
-    Ok(())
+        // A restarted sidecar starts with the limit in force, limiter only reacts to changes
+        format!("--max-overall-download-limit={}", current_speed_limit()),
+    ].into_iter().chain(tuning)).spawn().map_err(|e| process_err(e, "aria2c"))?;
+    ARIA2C_RUNNING.store(true, Ordering::SeqCst);
+    Ok((rx, track_child("aria2c", child)))
+}
+
+fn is_external() -> bool {
+    ARIA2C_EXTERNAL.load(Ordering::SeqCst)
+}
+
+fn rpc_endpoint() -> (String, String) {
+    let config = CONFIG.read().unwrap();
+    if is_external() {
+        (config.aria2_rpc.url.clone(), config.aria2_rpc.secret.clone())
+    } else {
+        (format!("http://127.0.0.1:{}/jsonrpc", ARIA2C_PORT.read().unwrap()), SECRET.read().unwrap().clone())
+    }
+}
+
+pub fn download_root() -> PathBuf {
+    let config = CONFIG.read().unwrap();
+    let rpc = &config.aria2_rpc;
+    if is_external() && config.download_backend == DownloadBackend::Aria2 {
+        // Without a local mapping, paths are only meaningful to the remote aria2
+        let dir = if rpc.local_dir.is_empty() { &rpc.remote_dir } else { &rpc.local_dir };
+        return PathBuf::from(dir);
+    }
+    drop(config);
+    temp_root()
+}
+
+fn to_remote(path: &Path) -> PathBuf {
+    let config = CONFIG.read().unwrap();
+    let rpc = &config.aria2_rpc;
+    if !is_external() || rpc.local_dir.is_empty() {
+        return path.to_path_buf();
+    }
+    match path.strip_prefix(&rpc.local_dir) {
+        Ok(relative) => PathBuf::from(&rpc.remote_dir).join(relative),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+pub fn is_local(path: &Path) -> bool {
+    let config = CONFIG.read().unwrap();
+    let rpc = &config.aria2_rpc;
+    if !path.is_absolute() {
+        return false;
+    }
+    !(is_external() && rpc.local_dir.is_empty() && !rpc.remote_dir.is_empty() && path.starts_with(&rpc.remote_dir))
 }
 
 pub async fn post_aria2c(action: &str, params: Vec<Value>) -> TauriResult<Value> {
-    let client = init_client_no_proxy().await?;
-    let mut params_vec = vec![Value::String(format!("token:{}", *SECRET.read().unwrap()))];
+    let client = &*ARIA2C_CLIENT;
+    let (url, secret) = rpc_endpoint();
+    let mut params_vec = vec![Value::String(format!("token:{secret}"))];
     for param in params {
         let value = match param {
             Value::String(s) => Ok(Value::String(s)),
@@ -471,7 +1555,7 @@ pub async fn post_aria2c(action: &str, params: Vec<Value>) -> TauriResult<Value>
         "params": params_vec
     });
     let response = client
-        .post(format!("http://127.0.0.1:{}/jsonrpc", ARIA2C_PORT.read().unwrap()))
+        .post(url)
         .timeout(Duration::from_millis(3000))
         .json(&payload).send().await?;
     if response.status() != StatusCode::OK {
@@ -483,6 +1567,122 @@ pub async fn post_aria2c(action: &str, params: Vec<Value>) -> TauriResult<Value>
     Ok(body)
 }
 
+async fn call_aria2c(action: &str, params: Vec<Value>) -> TauriResult<String> {
+    let body: Aria2General = serde_json::from_value(
+        post_aria2c(action, params).await?
+    ).with_context(|| format!("Failed to decode aria2c {action} response"))?;
+    if let Some(error) = body.error {
+        return Err(TauriError::new(error.message, Some(error.code)));
+    }
+    Ok(body.result.unwrap_or_default())
+}
+
+async fn tell_status(gid: &str) -> TauriResult<Option<Aria2TellStatusResult>> {
+    let body: Aria2TellStatus = serde_json::from_value(
+        post_aria2c("tellStatus", vec![json!(gid)]).await?
+    ).context("Failed to decode aria2c tellStatus response")?;
+    if let Some(error) = body.error {
+        if error.code == 1 { // GID is not found
+            return Ok(None);
+        }
+        return Err(TauriError::new(error.message, Some(error.code)));
+    }
+    Ok(body.result)
+}
+
+async fn get_status(task: &Task, gid: &str) -> TauriResult<Option<DownloadStatus>> {
+    // Hold queries back while the sidecar restarts, its gids are not registered yet
+    while task.backend == DownloadBackend::Aria2 && ARIA2C_RESTARTING.load(Ordering::SeqCst) {
+        sleep(Duration::from_millis(500)).await;
+    }
+    task.backend.status(gid).await
+}
+
+pub struct Aria2;
+
+impl Downloader for Aria2 {
+    async fn add(&self, urls: &[String], path: &Path, gid: Option<&str>, pause: bool, limit: Option<u64>) -> TauriResult<String> {
+        add_uri(urls, path, gid, pause, limit).await
+    }
+    async fn pause(&self, gid: &str) -> TauriResult<()> {
+        call_aria2c("pause", vec![json!(gid)]).await?;
+        Ok(())
+    }
+    async fn resume(&self, gid: &str) -> TauriResult<()> {
+        call_aria2c("unpause", vec![json!(gid)]).await?;
+        Ok(())
+    }
+    async fn remove(&self, gid: &str) -> TauriResult<()> {
+        call_aria2c("remove", vec![json!(gid)]).await?;
+        Ok(())
+    }
+    async fn forget(&self, gid: &str) -> TauriResult<()> {
+        call_aria2c("removeDownloadResult", vec![json!(gid)]).await?;
+        Ok(())
+    }
+    async fn status(&self, gid: &str) -> TauriResult<Option<DownloadStatus>> {
+        if !ARIA2C_RUNNING.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        Ok(tell_status(gid).await?.map(DownloadStatus::from))
+    }
+    async fn active(&self) -> TauriResult<Vec<DownloadStatus>> {
+        Ok(tell_active().await?.into_iter().map(DownloadStatus::from).collect())
+    }
+    async fn set_limit(&self, gid: &str, limit: Option<u64>) -> TauriResult<()> {
+        let options = json!({ "max-download-limit": limit.unwrap_or(0).to_string() });
+        call_aria2c("changeOption", vec![json!(gid), options]).await?;
+        Ok(())
+    }
+}
+
+async fn tell_active() -> TauriResult<Vec<Aria2TellStatusResult>> {
+    let keys = [
+        "gid", "status", "totalLength", "completedLength", "uploadLength",
+        "downloadSpeed", "uploadSpeed", "connections",
+    ];
+    let body: Aria2TellActive = serde_json::from_value(
+        post_aria2c("tellActive", vec![json!(keys)]).await?
+    ).context("Failed to decode aria2c tellActive response")?;
+    if let Some(error) = body.error {
+        return Err(TauriError::new(error.message, Some(error.code)));
+    }
+    Ok(body.result.unwrap_or_default())
+}
+
+async fn add_uri(
+    urls: &[String],
+    path: &Path,
+    gid: Option<&str>,
+    pause: bool,
+    limit: Option<u64>,
+) -> TauriResult<String> {
+    let path = to_remote(path);
+    // Relative paths are resolved against the dir configured on the remote aria2
+    let mut options = if path.is_absolute() {
+        json!({
+            "dir": path.parent(),
+            "out": path.file_name().map(|n| n.to_string_lossy()),
+        })
+    } else {
+        json!({ "out": path.to_string_lossy().replace('\\', "/") })
+    };
+    options["pause"] = json!(pause.to_string());
+    if let Some(gid) = gid {
+        options["gid"] = json!(gid);
+    }
+    if let Some(limit) = limit {
+        options["max-download-limit"] = json!(limit.to_string());
+    }
+    call_aria2c("addUri", vec![json!(urls), options]).await
+}
+
+fn is_downloaded(path: &Path) -> bool {
+    let mut control = path.as_os_str().to_owned();
+    control.push(".aria2");
+    path.exists() && !Path::new(&control).exists() && !native::control_path(path).exists()
+}
+
 fn check_breakpoint(
     input: &PathBuf,
     start_with: String
@@ -497,7 +1697,7 @@ fn check_breakpoint(
             for sub_entry in folder_entries {
                 let sub_path = sub_entry?.path();
                 let file_name = sub_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-                if file_name.ends_with("aria2") {
+                if file_name.ends_with("aria2") || file_name.ends_with(native::CONTROL_EXT) {
                     return Ok(Some(folder_path))
                 }
             }
@@ -506,13 +1706,59 @@ fn check_breakpoint(
     Ok(None)
 }
 
+async fn content_length(urls: &[String]) -> Option<u64> {
+    let client = init_client().await.ok()?;
+    for url in urls {
+        let Ok(resp) = client.head(url).send().await else { continue };
+        // reqwest reports an empty body for HEAD, so the header is read directly
+        let len = resp.headers().get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse().ok());
+        if resp.status().is_success() && len.is_some() {
+            return len;
+        }
+    }
+    None
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn convert(id: String, input: String, profile: String) -> TauriResult<String> {
+    let profile = CONFIG.read().unwrap().profiles.iter()
+        .find(|v| v.name == profile).cloned()
+        .ok_or(anyhow!("Transcode profile {profile} not found"))?;
+    if CANCEL_TOKENS.read().unwrap().contains_key(&id) {
+        return Err(anyhow!("{id} is already being processed").into());
+    }
+    let input = PathBuf::from(input);
+    let name = profile.name.replace(['\\', '/', ':', '*', '?', '"', '<', '>', '|'], "_");
+    let output = input.with_file_name(format!(
+        "{}_{name}.{}",
+        input.file_stem().unwrap_or_default().to_string_lossy(),
+        input.extension().unwrap_or_default().to_string_lossy(),
+    ));
+    // The source size is a fair upper bound for a compatibility transcode
+    check_space(vec![(output.parent().unwrap().to_path_buf(), fs::metadata(&input)?.len())]).await?;
+    let token = CancelToken::register(&id);
+    let result = tokio::select! {
+        result = ffmpeg::convert(Arc::new(id.clone()), &input, &output, &profile, &token) => result,
+        _ = token.cancelled() => Err(anyhow!("Conversion was cancelled")),
+    };
+    CANCEL_TOKENS.write().unwrap().remove(&id);
+    if let Err(e) = result {
+        let _ = fs::remove_file(&output);
+        return Err(e.context("Failed to convert").into());
+    }
+    Ok(output.to_string_lossy().into())
+}
+
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn push_back_queue(
     info: Arc<ArchiveInfo>,
     select: CurrentSelect,
-    tasks: Vec<Task>,
+    mut tasks: Vec<Task>,
     parent: Option<String>,
+    speed_limit: Option<u64>,
 ) -> TauriResult<PathBuf> {
     let mut parent = if let Some(parent) = &parent {
         PathBuf::from(parent)
@@ -532,6 +1778,15 @@ pub async fn push_back_queue(
             count += 1;
         }
     }
+    let processed = tasks.iter().any(|v| matches!(v.task_type, TaskType::Merge | TaskType::Flac));
+    let mut size = 0;
+    for task in tasks.iter_mut() {
+        if let (None, Some(urls)) = (task.size, &task.urls) {
+            task.size = content_length(urls).await;
+        }
+        size += task.size.unwrap_or(0);
+    }
+    check_space(space_needs(Some(&download_root()), &parent, size, (0, 0), processed)).await?;
     fs::create_dir_all(&parent).context("Failed to create output folder")?;
     let mut queue_info = QueueInfo {
         id: random_string(16),
@@ -539,6 +1794,12 @@ pub async fn push_back_queue(
         output: parent.join(&info.filename),
         info: info.clone(),
         select,
+        error: None,
+        speed_limit,
+        priority: 0,
+        paused: false,
+        size: (size > 0).then_some(size),
+        verification: None,
     };
     for task in &mut queue_info.tasks {
         if task.task_type == TaskType::Merge || task.task_type == TaskType::Flac || task.urls.is_none() { // Non-download task
@@ -548,68 +1809,170 @@ pub async fn push_back_queue(
         let urls = task.urls.clone().unwrap();
         let parsed_url = reqwest::Url::parse(&urls[0])?;
         let name = parsed_url.path_segments().unwrap().last().unwrap();
-        let temp_dir = { CONFIG.read().unwrap().temp_dir.join("com.btjawa.bilitools") };
-        fs::create_dir_all(&temp_dir).context("Failed to create app temp dir")?;
-        let dir = check_breakpoint(
-            &temp_dir, format!("{}_{}", &info.filename, info.ts.millis)
-        )?.unwrap_or(temp_dir.join(format!("{}_{}", &info.filename, info.ts.millis)));
-        let params = vec![json!(urls), json!({ "dir": dir, "out": name, "pause": "true" })];
-        let body: Aria2General = serde_json::from_value(
-            post_aria2c("addUri", params).await?
-        ).context("Failed to decode aria2c addUri response")?;
-        if let Some(error) = body.error {
-            return Err(TauriError::new(error.message, Some(error.code)));
-        }
-        let gid = body.result.unwrap_or(String::new());
-        task.gid = Some(gid);
-        task.path = Some(dir.join(name));
+        let temp_dir = download_root();
+        let folder = format!("{}_{}", &info.filename, info.ts.millis);
+        let dir = if is_local(&temp_dir) {
+            fs::create_dir_all(&temp_dir).context("Failed to create app temp dir")?;
+            check_breakpoint(&temp_dir, folder.clone())?.unwrap_or(temp_dir.join(folder))
+        } else {
+            temp_dir.join(folder)
+        };
+        let path = dir.join(name);
+        task.backend = CONFIG.read().unwrap().download_backend;
+        task.gid = Some(task.backend.add(&urls, &path, None, true, speed_limit).await?);
+        task.path = Some(path);
+    }
+    let mut archive = (*queue_info.info).clone();
+    // Chapters come in over IPC, an inverted or empty one would break the split and the metadata
+    archive.chapters.retain(|v| v.end > v.start);
+    // Track contents are kept next to the downloads instead of in the persisted queue
+    let dir = queue_info.tasks.iter().find_map(|v| v.path.as_deref()?.parent()).filter(|v| is_local(v));
+    for (index, track) in archive.subtitles.iter_mut().enumerate() {
+        let (Some(dir), Some(data)) = (dir, track.data.take()) else { continue };
+        let ext = match track.kind {
+            SubtitleKind::Srt => "srt",
+            SubtitleKind::Danmaku => "xml",
+        };
+        let path = dir.join(format!("track_{index}.{ext}"));
+        fs::create_dir_all(dir)?;
+        fs::write(&path, data).context("Failed to write subtitle track")?;
+        track.path = Some(path);
     }
+    archive.subtitles.retain(|v| v.path.is_some());
+    queue_info.info = Arc::new(archive);
     QUEUE_MANAGER.push_back(Arc::new(queue_info), QueueType::Waiting).await?;
     QUEUE_MANAGER.update(QueueType::Waiting).await;
     Ok(parent)
 }
 
-#[tauri::command(async)]
-#[specta::specta]
-pub async fn process_queue(event: Channel<DownloadEvent>) -> TauriResult<()> {
-    let manager = Arc::new(DownloadManager::new(event));
+pub async fn scheduler() {
+    let manager = Arc::new(DownloadManager::new());
     let (result_tx, mut result_rx) = mpsc::channel::<Result<Arc<QueueInfo>, TauriError>>(100);
     let tx_arc = Arc::new(result_tx);
     loop {
-        if QUEUE_MANAGER.get_len(QueueType::Waiting).await > 0 {
-            manager.clone().process_tasks(tx_arc.clone()).await?;
+        let active = SCHEDULER_ACTIVE.load(Ordering::SeqCst) && !SCHEDULER_PAUSED.load(Ordering::SeqCst)
+            && !*SHUTTING_DOWN.read().unwrap();
+        if active && QUEUE_MANAGER.get_len(QueueType::Waiting).await > 0 && has_space() {
+            if let Err(e) = manager.clone().process_tasks(tx_arc.clone()).await {
+                process_err(TauriError::from(e), "aria2c");
+            }
         }
         tokio::select! {
             Some(result) = result_rx.recv() => {
+                let idle = QUEUE_MANAGER.get_len(QueueType::Doing).await == 0
+                    && QUEUE_MANAGER.get_len(QueueType::Waiting).await == 0;
+                if idle {
+                    SCHEDULER_ACTIVE.store(false, Ordering::SeqCst);
+                }
                 match result {
                     Err(e) => { process_err(e, "aria2c"); },
-                    Ok(r) => if QUEUE_MANAGER.get_len(QueueType::Doing).await == 0 {
+                    Ok(r) => if idle {
                         let parent = r.output.parent().unwrap().to_string_lossy();
-                        break notifica::notify("BiliTools", &format!("{parent}\nDownload Complete."))?;
+                        if let Err(e) = notifica::notify("BiliTools", &format!("{parent}\nDownload Complete.")) {
+                            log::warn!("Failed to send notification: {e}");
+                        }
                     }
                 }
             }
-            else => ()
+            _ = SCHEDULER_WAKE.notified() => (),
+            // Periodic wake-up so that changes to max_conc are picked up
+            _ = sleep(Duration::from_secs(1)) => (),
         }
     }
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn process_queue() -> TauriResult<()> {
+    wake_scheduler();
     Ok(())
 }
 
+// Starting or resuming anything lifts a Pause All
+fn wake_scheduler() {
+    SCHEDULER_PAUSED.store(false, Ordering::SeqCst);
+    SCHEDULER_ACTIVE.store(true, Ordering::SeqCst);
+    SCHEDULER_WAKE.notify_one();
+}
+
 #[tauri::command(async)]
 #[specta::specta]
-pub async fn remove_task(id: String, queue_type: QueueType, gid: Option<String>) -> TauriResult<()> {
+pub async fn get_queue_state() -> TauriResult<QueueState> {
+    let progress = ITEM_PROGRESS.read().unwrap().iter().map(|(id, item)| {
+        let (progress, remaining) = aggregate(item);
+        let tasks = item.tasks.iter().map(|(gid, v)| TaskState {
+            gid: gid.clone(),
+            task_type: v.task_type.clone(),
+            content_length: v.content_length,
+            chunk_length: v.chunk_length,
+            speed: v.speed,
+            finished: v.finished,
+        }).collect();
+        (id.clone(), ItemState {
+            stage: item.stage.as_ref().map(TaskStage::from),
+            progress, remaining, tasks,
+        })
+    }).collect();
+    Ok(QueueState {
+        waiting: QUEUE_MANAGER.get(QueueType::Waiting).await,
+        doing: QUEUE_MANAGER.get(QueueType::Doing).await,
+        complete: QUEUE_MANAGER.get(QueueType::Complete).await,
+        failed: QUEUE_MANAGER.get(QueueType::Failed).await,
+        paused: QUEUE_MANAGER.get(QueueType::Paused).await,
+        progress,
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn subscribe_progress(event: Channel<DownloadEvent>) -> u32 {
+    DOWNLOAD_EVENTS.subscribe(event)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn unsubscribe_progress(id: u32) {
+    DOWNLOAD_EVENTS.unsubscribe(id);
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn remove_task(id: String, queue_type: QueueType) -> TauriResult<()> {
     match queue_type {
         QueueType::Complete => {
+            // Stops a conversion that is running on the item
+            let token = CANCEL_TOKENS.read().unwrap().get(&id).cloned();
+            if let Some(token) = token {
+                token.cancel();
+                DOWNLOAD_EVENTS.send(DownloadEvent::Cancelled { id: Arc::new(id.clone()) });
+            }
             downloads::delete(id.clone()).await?;
         },
-        _ => {
-            if let Some(gid) = gid {
-                let body: Aria2General = serde_json::from_value(
-                    post_aria2c("remove", vec![json!(gid)]).await?
-                ).context("Failed to decode aria2c remove response")?;
-                if let Some(error) = body.error {
-                    return Err(TauriError::new(error.message, Some(error.code)));
+        QueueType::Failed => {
+            if let Some(info) = QUEUE_MANAGER.get(QueueType::Failed).await.iter().find(|v| v.id == id) {
+                for task in info.tasks.iter().filter(|v| v.urls.is_some()) {
+                    if let Some(gid) = &task.gid {
+                        let _ = task.backend.forget(gid).await;
+                    }
                 }
+                cleanup(info).await;
+            }
+        },
+        _ => {
+            let token = CANCEL_TOKENS.read().unwrap().get(&id).cloned();
+            if token.as_ref().is_some_and(|v| !v.cancel()) {
+                return Err(anyhow!("{id} is being finalized, remove it once it has completed").into());
+            }
+            if let Some(info) = QUEUE_MANAGER.get(queue_type).await.iter().find(|v| v.id == id) {
+                let gids = info.tasks.iter()
+                    .filter(|v| v.urls.is_some())
+                    .filter_map(|v| Some((v.backend, v.gid.clone()?)));
+                stop_gids(gids.collect()).await;
+                // Waiting items already hold their temp dir and output folder from enqueue
+                cleanup(info).await;
+            }
+            if token.is_some() {
+                DOWNLOAD_EVENTS.send(DownloadEvent::Cancelled { id: Arc::new(id.clone()) });
             }
         }
     }
@@ -617,15 +1980,175 @@ pub async fn remove_task(id: String, queue_type: QueueType, gid: Option<String>)
     Ok(())
 }
 
+async fn stop_gids(gids: Vec<(DownloadBackend, String)>) {
+    for (backend, gid) in &gids {
+        let _ = backend.remove(gid).await;
+    }
+    // Wait for the files to be released before they are deleted
+    let deadline = Instant::now() + Duration::from_secs(5);
+    for (backend, gid) in &gids {
+        while Instant::now() < deadline {
+            match backend.status(gid).await {
+                Ok(Some(status)) if status.state == DownloadState::Active => sleep(Duration::from_millis(250)).await,
+                _ => break,
+            }
+        }
+        let _ = backend.forget(gid).await;
+    }
+}
+
 #[tauri::command(async)]
 #[specta::specta]
 pub async fn toggle_pause(pause: bool, gid: String) -> TauriResult<()> {
-    let action = if pause { "pause" } else { "unpause" };
-    let body: Aria2General = serde_json::from_value(
-        post_aria2c(action, vec![json!(gid)]).await?
-    )?;
-    if let Some(error) = body.error {
-        return Err(TauriError::new(error.message, Some(error.code)));
+    let task = QUEUE_MANAGER.find_task(&gid).await
+        .ok_or(anyhow!("No queue item owns {gid}"))?;
+    if pause {
+        task.backend.pause(&gid).await
+    } else {
+        task.backend.resume(&gid).await
+    }
+}
+
+async fn requeue_failed(id: Option<String>) -> TauriResult<()> {
+    let failed = QUEUE_MANAGER.get(QueueType::Failed).await;
+    for info in failed.iter().filter(|v| id.as_ref().is_none_or(|id| v.id == *id)) {
+        // Re-register errored gids at the same paths so aria2c resumes from partial files
+        reconcile(info, &[DownloadBackend::Aria2, DownloadBackend::Native]).await?;
+        let mut info = (**info).clone();
+        info.error = None;
+        QUEUE_MANAGER.retain(QueueType::Failed, info.id.clone()).await?;
+        QUEUE_MANAGER.push_back(Arc::new(info), QueueType::Waiting).await?;
     }
+    QUEUE_MANAGER.update(QueueType::Waiting).await;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn retry_task(id: String) -> TauriResult<()> {
+    requeue_failed(Some(id)).await
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn retry_all_failed() -> TauriResult<()> {
+    requeue_failed(None).await
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn set_speed_limit(id: String, limit: Option<u64>) -> TauriResult<()> {
+    let (_, info) = QUEUE_MANAGER.modify(&id, |info| info.speed_limit = limit).await
+        .ok_or(anyhow!("Queue item {id} not found"))?;
+    for task in info.tasks.iter().filter(|v| v.urls.is_some()) {
+        let Some(gid) = &task.gid else { continue };
+        // Finished or not yet registered gids reject option changes, which is fine
+        if let Err(e) = task.backend.set_limit(gid, limit).await {
+            log::warn!("Failed to change speed limit of {gid}: {e}");
+        }
+    }
+    Ok(())
+}
+
+async fn sync_positions() {
+    let mut waiting: Vec<_> = QUEUE_MANAGER.get(QueueType::Waiting).await.into_iter().collect();
+    // Stable sort keeps queue order within the same priority, matching waiting_to_doing
+    waiting.sort_by_key(|v| std::cmp::Reverse(v.priority));
+    let gids = waiting.iter()
+        .flat_map(|v| v.tasks.iter())
+        // Native downloads have no queue of their own, they only start when unpaused
+        .filter(|v| v.urls.is_some() && v.backend == DownloadBackend::Aria2)
+        .filter_map(|v| v.gid.clone());
+    for (pos, gid) in gids.enumerate() {
+        if let Err(e) = call_aria2c("changePosition", vec![json!(gid), json!(pos), json!("POS_SET")]).await {
+            log::warn!("Failed to change position of {gid}: {e}");
+        }
+    }
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn move_task(id: String, position: QueuePosition) -> TauriResult<()> {
+    QUEUE_MANAGER.move_to(&id, position).await?;
+    sync_positions().await;
+    Ok(())
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn set_priority(id: String, priority: i32) -> TauriResult<()> {
+    QUEUE_MANAGER.modify(&id, |info| info.priority = priority).await
+        .ok_or(anyhow!("Queue item {id} not found"))?;
+    sync_positions().await;
+    Ok(())
+}
+
+async fn set_item_gids_paused(info: &QueueInfo, pause: bool) {
+    let active = ACTIVE_GIDS.read().unwrap().clone();
+    for task in info.tasks.iter().filter(|v| v.urls.is_some()) {
+        let Some(gid) = &task.gid else { continue };
+        // Only the gid being awaited may run again, later ones are started in order by process_task
+        if !pause && !active.contains_key(gid) {
+            continue;
+        }
+        let result = if pause { task.backend.pause(gid).await } else { task.backend.resume(gid).await };
+        if let Err(e) = result {
+            log::warn!("Failed to {} {gid}: {e}", if pause { "pause" } else { "resume" });
+        }
+    }
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn pause_item(id: String) -> TauriResult<()> {
+    if QUEUE_MANAGER.transfer(&id, QueueType::Waiting, QueueType::Paused, |v| v.paused = true).await.is_some() {
+        return Ok(());
+    }
+    let (queue_type, info) = QUEUE_MANAGER.modify(&id, |v| v.paused = true).await
+        .ok_or(anyhow!("Queue item {id} not found"))?;
+    if queue_type == QueueType::Doing {
+        set_item_gids_paused(&info, true).await;
+    }
+    Ok(())
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn resume_item(id: String) -> TauriResult<()> {
+    // The scheduler may have gone idle while the item was paused
+    wake_scheduler();
+    if QUEUE_MANAGER.transfer(&id, QueueType::Paused, QueueType::Waiting, |v| v.paused = false).await.is_some() {
+        return Ok(());
+    }
+    let (queue_type, info) = QUEUE_MANAGER.modify(&id, |v| v.paused = false).await
+        .ok_or(anyhow!("Queue item {id} not found"))?;
+    if queue_type == QueueType::Doing {
+        set_item_gids_paused(&info, false).await;
+    }
+    Ok(())
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn pause_all() -> TauriResult<()> {
+    // Keeps items that are added afterwards from starting until something is resumed
+    SCHEDULER_PAUSED.store(true, Ordering::SeqCst);
+    for queue_type in [QueueType::Waiting, QueueType::Doing] {
+        for info in QUEUE_MANAGER.get(queue_type).await.iter().filter(|v| !v.paused) {
+            pause_item(info.id.clone()).await?;
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn resume_all() -> TauriResult<()> {
+    wake_scheduler();
+    for queue_type in [QueueType::Doing, QueueType::Paused] {
+        for info in QUEUE_MANAGER.get(queue_type).await.iter().filter(|v| v.paused) {
+            resume_item(info.id.clone()).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use std::{fs, path::{Path, PathBuf}, sync::atomic::{AtomicBool, Ordering}};
+use serde::Serialize;
+use lazy_static::lazy_static;
+use tauri_specta::Event;
+use anyhow::anyhow;
+use specta::Type;
+
+use super::aria2c::{download_root, is_local, QueueInfo, QueueType, TaskType, QUEUE_MANAGER};
+use crate::{shared::{available_space, get_app_handle, same_volume, CONFIG}, TauriResult};
+
+lazy_static! {
+    static ref LOW_SPACE: AtomicBool = AtomicBool::new(false);
+}
+
+#[derive(Clone, Serialize, Type, Event)]
+#[serde(tag = "status")]
+pub enum DiskSpace {
+    Low {
+        path: PathBuf,
+        available: u64,
+        threshold: u64,
+    },
+    Recovered,
+}
+
+// Bytes still needed per directory: the downloads in the temp folder, plus
+// the output unless a lone stream is just renamed on the same volume
+pub fn space_needs(temp: Option<&Path>, output: &Path, size: u64, written: (u64, u64), processed: bool) -> Vec<(PathBuf, u64)> {
+    let mut needs = Vec::new();
+    if let Some(temp) = temp.filter(|v| is_local(v)) {
+        needs.push((temp.to_path_buf(), size.saturating_sub(written.0)));
+        if !processed && same_volume(temp, output) {
+            return needs;
+        }
+    }
+    needs.push((output.to_path_buf(), size.saturating_sub(written.1)));
+    needs
+}
+
+fn item_needs(info: &QueueInfo) -> Vec<(PathBuf, u64)> {
+    let Some(size) = info.size else { return Vec::new() };
+    let temp = info.tasks.iter().find_map(|v| v.path.as_deref()?.parent());
+    let output = info.output.parent().unwrap_or(&info.output);
+    let processed = info.tasks.iter().any(|v| matches!(v.task_type, TaskType::Merge | TaskType::Flac));
+    // Space that is already allocated on disk is no longer free, so it isn't reserved twice
+    let len = |path: &Path| fs::metadata(path).map(|v| v.len()).unwrap_or(0);
+    let downloaded = info.tasks.iter().filter_map(|v| v.path.as_deref()).map(len).sum();
+    space_needs(temp, output, size, (downloaded, len(&info.output)), processed)
+}
+
+async fn reserved_on(dir: &Path) -> u64 {
+    let mut reserved = 0;
+    for queue_type in [QueueType::Waiting, QueueType::Doing, QueueType::Paused] {
+        for info in QUEUE_MANAGER.get(queue_type).await.iter() {
+            reserved += item_needs(info).into_iter()
+                .filter(|(v, _)| same_volume(v, dir))
+                .map(|(_, need)| need).sum::<u64>();
+        }
+    }
+    reserved
+}
+
+pub async fn check_space(needs: Vec<(PathBuf, u64)>) -> TauriResult<()> {
+    let threshold = CONFIG.read().unwrap().min_free_space;
+    // Needs on the same volume add up, e.g. the downloads and the merged output
+    let mut volumes: Vec<(PathBuf, u64)> = Vec::new();
+    for (dir, need) in needs {
+        match volumes.iter_mut().find(|(v, _)| same_volume(v, &dir)) {
+            Some((_, total)) => *total += need,
+            None => volumes.push((dir, need)),
+        }
+    }
+    for (dir, need) in volumes {
+        let available = available_space(&dir)?.saturating_sub(reserved_on(&dir).await);
+        if available < need + threshold {
+            return Err(anyhow!(
+                "Not enough disk space in {}: {need} bytes needed, {available} bytes available after reservations",
+                dir.display()
+            ).into());
+        }
+    }
+    Ok(())
+}
+
+fn low_space() -> Option<(PathBuf, u64, u64)> {
+    let (threshold, down_dir) = {
+        let config = CONFIG.read().unwrap();
+        (config.min_free_space, config.down_dir.clone())
+    };
+    if threshold == 0 {
+        return None;
+    }
+    [download_root(), down_dir].into_iter().filter(|v| is_local(v)).find_map(|dir| {
+        let available = available_space(&dir).ok()?;
+        (available < threshold).then_some((dir, available, threshold))
+    })
+}
+
+pub fn has_space() -> bool {
+    let low = low_space();
+    let was_low = LOW_SPACE.swap(low.is_some(), Ordering::SeqCst);
+    match low {
+        Some((path, available, threshold)) => {
+            if !was_low {
+                log::warn!("Free space in {} is below {threshold} bytes, holding the queue", path.display());
+                DiskSpace::Low { path, available, threshold }.emit(&get_app_handle()).unwrap();
+            }
+            false
+        },
+        None => {
+            if was_low {
+                DiskSpace::Recovered.emit(&get_app_handle()).unwrap();
+            }
+            true
+        },
+    }
+}
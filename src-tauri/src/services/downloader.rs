@@ -0,0 +1,112 @@
+use std::{future::Future, path::Path};
+use tokio::sync::broadcast;
+use lazy_static::lazy_static;
+
+use super::{aria2c::Aria2, native::Native};
+use crate::{config::DownloadBackend, TauriResult};
+
+lazy_static! {
+    static ref STATE_CHANGES: broadcast::Sender<StateChange> = broadcast::channel(256).0;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadState {
+    Waiting,
+    Active,
+    Paused,
+    Complete,
+    Error,
+    Removed,
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadStatus {
+    pub gid: String,
+    pub state: DownloadState,
+    pub total_length: u64,
+    pub completed_length: u64,
+    pub download_speed: u64,
+    pub connections: u64,
+    pub error_code: Option<isize>,
+    pub error_message: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    pub gid: String,
+    pub state: DownloadState,
+}
+
+pub fn notify(gid: &str, state: DownloadState) {
+    let _ = STATE_CHANGES.send(StateChange { gid: gid.into(), state });
+}
+
+pub fn subscribe() -> broadcast::Receiver<StateChange> {
+    STATE_CHANGES.subscribe()
+}
+
+// A gid is only ever handed to the backend that created it, which is recorded on its task
+pub trait Downloader {
+    fn add(
+        &self, urls: &[String], path: &Path, gid: Option<&str>, pause: bool, limit: Option<u64>,
+    ) -> impl Future<Output = TauriResult<String>> + Send;
+    fn pause(&self, gid: &str) -> impl Future<Output = TauriResult<()>> + Send;
+    fn resume(&self, gid: &str) -> impl Future<Output = TauriResult<()>> + Send;
+    fn remove(&self, gid: &str) -> impl Future<Output = TauriResult<()>> + Send;
+    // Drops a stopped download, so that its gid can be added again
+    fn forget(&self, gid: &str) -> impl Future<Output = TauriResult<()>> + Send;
+    fn status(&self, gid: &str) -> impl Future<Output = TauriResult<Option<DownloadStatus>>> + Send;
+    fn active(&self) -> impl Future<Output = TauriResult<Vec<DownloadStatus>>> + Send;
+    fn set_limit(&self, gid: &str, limit: Option<u64>) -> impl Future<Output = TauriResult<()>> + Send;
+}
+
+impl Downloader for DownloadBackend {
+    async fn add(&self, urls: &[String], path: &Path, gid: Option<&str>, pause: bool, limit: Option<u64>) -> TauriResult<String> {
+        match self {
+            DownloadBackend::Aria2 => Aria2.add(urls, path, gid, pause, limit).await,
+            DownloadBackend::Native => Native.add(urls, path, gid, pause, limit).await,
+        }
+    }
+    async fn pause(&self, gid: &str) -> TauriResult<()> {
+        match self {
+            DownloadBackend::Aria2 => Aria2.pause(gid).await,
+            DownloadBackend::Native => Native.pause(gid).await,
+        }
+    }
+    async fn resume(&self, gid: &str) -> TauriResult<()> {
+        match self {
+            DownloadBackend::Aria2 => Aria2.resume(gid).await,
+            DownloadBackend::Native => Native.resume(gid).await,
+        }
+    }
+    async fn remove(&self, gid: &str) -> TauriResult<()> {
+        match self {
+            DownloadBackend::Aria2 => Aria2.remove(gid).await,
+            DownloadBackend::Native => Native.remove(gid).await,
+        }
+    }
+    async fn forget(&self, gid: &str) -> TauriResult<()> {
+        match self {
+            DownloadBackend::Aria2 => Aria2.forget(gid).await,
+            DownloadBackend::Native => Native.forget(gid).await,
+        }
+    }
+    async fn status(&self, gid: &str) -> TauriResult<Option<DownloadStatus>> {
+        match self {
+            DownloadBackend::Aria2 => Aria2.status(gid).await,
+            DownloadBackend::Native => Native.status(gid).await,
+        }
+    }
+    async fn active(&self) -> TauriResult<Vec<DownloadStatus>> {
+        match self {
+            DownloadBackend::Aria2 => Aria2.active().await,
+            DownloadBackend::Native => Native.active().await,
+        }
+    }
+    async fn set_limit(&self, gid: &str, limit: Option<u64>) -> TauriResult<()> {
+        match self {
+            DownloadBackend::Aria2 => Aria2.set_limit(gid, limit).await,
+            DownloadBackend::Native => Native.set_limit(gid, limit).await,
+        }
+    }
+}
@@ -1,20 +1,31 @@
 use tokio::{fs, io::{self, AsyncBufReadExt, AsyncSeekExt}, time::sleep};
-use std::{io::SeekFrom, path::PathBuf, sync::Arc, time::Duration};
+use std::{io::SeekFrom, path::{Path, PathBuf}, sync::Arc, time::Duration};
 use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
-use tauri::{ipc::Channel, Manager};
-use tauri_plugin_shell::ShellExt;
+use tauri::Manager;
+use tauri_plugin_shell::{process::CommandEvent, ShellExt};
 use regex::Regex;
 
 use super::aria2c::{
+    send_progress,
+    ArchiveInfo,
+    CancelToken,
+    SubtitleKind,
+    SubtitleTrack,
+    DOWNLOAD_EVENTS,
     Task,
     TaskType,
     QueueInfo,
+    DownloadChannels,
     DownloadEvent,
 };
 
-use crate::{shared::{get_app_handle, process_err, get_ts}, TauriError, TauriResult};
+use crate::{
+    config::{AudioCodec, ChapterMode, TranscodeProfile, VideoCodec},
+    shared::{get_app_handle, init_client, process_err, get_ts, track_child, untrack_child, CONFIG},
+    TauriError, TauriResult
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct FFmpegLog {
@@ -32,19 +43,62 @@ struct FFmpegLog {
     progress: String,
 }
 
-pub async fn get_stream_info(video: PathBuf, audio: PathBuf) -> Result<(u64, String)> {
+#[derive(Default)]
+struct FFmpegOutput {
+    code: Option<i32>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl FFmpegOutput {
+    fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+async fn run<I, S>(args: I, token: &CancelToken) -> Result<FFmpegOutput>
+where I: IntoIterator<Item = S>, S: AsRef<std::ffi::OsStr> {
+    run_sidecar("ffmpeg", args, token).await
+}
+
+async fn run_sidecar<I, S>(name: &str, args: I, token: &CancelToken) -> Result<FFmpegOutput>
+where I: IntoIterator<Item = S>, S: AsRef<std::ffi::OsStr> {
     let app = get_app_handle();
-    let meta_output = app.shell().sidecar("ffmpeg")?
-        .args([
-            "-i", video.to_str().unwrap(),
-            "-i", audio.to_str().unwrap(),
-            "-map", "0:v:0",
-            "-map", "1:a:0",
-            "-c", "copy",
-            "-f", "null", "-",
-        ])
-        .output()
-        .await?;
+    let (mut rx, child) = app.shell().sidecar(name)?.args(args).spawn()?;
+    // Tracked so that shutdown can wait for or kill it
+    let pid = track_child(name, child);
+    token.attach(pid);
+    let mut output = FFmpegOutput::default();
+    while let Some(event) = rx.recv().await {
+        match event {
+            CommandEvent::Stdout(line) => {
+                output.stdout.extend(line);
+                output.stdout.push(b'\n');
+            },
+            CommandEvent::Stderr(line) => {
+                output.stderr.extend(line);
+                output.stderr.push(b'\n');
+            },
+            CommandEvent::Terminated(payload) => {
+                output.code = payload.code;
+                break;
+            },
+            _ => (),
+        }
+    }
+    untrack_child(pid);
+    Ok(output)
+}
+
+pub async fn get_stream_info(video: PathBuf, audio: PathBuf, token: &CancelToken) -> Result<(u64, String)> {
+    let meta_output = run([
+        "-i", video.to_str().unwrap(),
+        "-i", audio.to_str().unwrap(),
+        "-map", "0:v:0",
+        "-map", "1:a:0",
+        "-c", "copy",
+        "-f", "null", "-",
+    ], token).await?;
 
     let stderr = String::from_utf8_lossy(&meta_output.stderr);
     log::info!("FFmpeg stderr:\n{}", &stderr);
@@ -63,14 +117,47 @@ pub async fn get_stream_info(video: PathBuf, audio: PathBuf) -> Result<(u64, Str
     Ok((video_frames, audio_codec))
 }
 
-pub async fn merge(info: Arc<QueueInfo>, event: &Channel<DownloadEvent>) -> TauriResult<()> {
+struct Probe {
+    // None when the input carries no video besides an attached picture
+    frames: Option<u64>,
+    duration_ms: u64,
+    video_codec: Option<String>,
+}
+
+async fn probe(input: &Path, token: &CancelToken) -> Result<Probe> {
+    let output = run([
+        "-i", input.to_str().unwrap(),
+        "-map", "0", "-c", "copy",
+        "-f", "null", "-",
+    ], token).await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let video_codec = Regex::new(r"Video:\s*(\w+)[^\n]*")?
+        .captures_iter(&stderr)
+        .find(|caps| !caps[0].contains("attached pic"))
+        .map(|caps| caps[1].to_string());
+    let frame = Regex::new(r"frame=\s*(\d+)")?;
+    let frames = video_codec.as_ref().and_then(|_| frame.captures_iter(&stderr)
+        .filter_map(|caps| caps.get(1)?.as_str().parse::<u64>().ok())
+        .max());
+    let duration_ms = Regex::new(r"Duration:\s*(\d+):(\d+):(\d+(?:\.\d+)?)")?
+        .captures(&stderr)
+        .and_then(|caps| {
+            let hours = caps[1].parse::<f64>().ok()?;
+            let minutes = caps[2].parse::<f64>().ok()?;
+            let seconds = caps[3].parse::<f64>().ok()?;
+            Some(((hours * 3600.0 + minutes * 60.0 + seconds) * 1000.0) as u64)
+        });
+    // Video progress is counted in frames, so only audio relies on the duration
+    let Some(duration_ms) = duration_ms.or(frames.map(|_| 0)) else {
+        return Err(anyhow!("Failed to parse duration"));
+    };
+    Ok(Probe { frames, duration_ms, video_codec })
+}
+
+pub async fn merge(info: Arc<QueueInfo>, event: &DownloadChannels, token: &CancelToken) -> TauriResult<()> {
     if info.tasks.len() < 2 {
         return Err(anyhow!("Insufficient number of input paths, {}", info.tasks.len()).into());
     }
-    let paths = {
-        let _paths: Vec<_> = info.tasks.iter().map(|v| v.path.clone()).collect();
-        Arc::new(_paths)
-    };
     let output = info.output.clone();
     let video_path = info.tasks.iter()
         .find(|v| v.task_type == TaskType::Video)
@@ -88,7 +175,7 @@ pub async fn merge(info: Arc<QueueInfo>, event: &Channel<DownloadEvent>) -> Taur
     fs::create_dir_all(progress_path.parent().unwrap()).await
         .context("Failed to create FFmpeg progress Folder")?;
 
-    let stream_info = get_stream_info(video_path, audio_path).await
+    let stream_info = get_stream_info(video_path.clone(), audio_path.clone(), token).await
         .context("Failed to get stream info")
         .map_err(|e| process_err(TauriError::from(e), "ffmpeg"))?;
 
@@ -103,70 +190,426 @@ pub async fn merge(info: Arc<QueueInfo>, event: &Channel<DownloadEvent>) -> Taur
         },
         _ => "copy",
     };
+    let cover = match ext {
+        "mp4" | "mkv" => fetch_cover(&info).await,
+        _ => None,
+    };
+    let chapter_mode = CONFIG.read().unwrap().chapters;
+    let chapters = match ext {
+        "mp4" | "mkv" if chapter_mode != ChapterMode::Off => write_chapters(&info).await,
+        _ => None,
+    };
+    let mut inputs = vec![video_path.clone(), audio_path.clone()];
+    let mut args: Vec<String> = vec!["-map".into(), "0:v:0".into(), "-map".into(), "1:a:0".into()];
+    match (ext, &cover) {
+        ("mp4", Some(cover)) => {
+            args.extend([
+                "-map".into(), format!("{}:v:0", inputs.len()),
+                "-disposition:v:1".into(), "attached_pic".into(),
+            ]);
+            inputs.push(cover.clone());
+        },
+        ("mkv", Some(cover)) => args.extend([
+            "-attach".into(), cover.to_string_lossy().into(),
+            "-metadata:s:t:0".into(), format!("mimetype={}", cover_mime(cover)),
+            "-metadata:s:t:0".into(), format!("filename={}", cover.file_name().unwrap().to_string_lossy()),
+        ]),
+        _ => (),
+    }
+    if let Some(chapters) = &chapters {
+        args.extend(["-map_chapters".into(), inputs.len().to_string()]);
+        inputs.push(chapters.clone());
+    }
+    let (burned, mut tracks): (Vec<_>, Vec<_>) = subtitle_tracks(&info, token).await
+        .into_iter().partition(|(track, _)| track.burn);
+    if ext == "flv" {
+        tracks.clear();
+    }
+    for (index, (track, path)) in tracks.iter().enumerate() {
+        args.extend([
+            "-map".into(), format!("{}:s:0", inputs.len()),
+            format!("-metadata:s:s:{index}"), format!("language={}", track.lang),
+            format!("-metadata:s:s:{index}"), format!("title={}", track.title),
+            format!("-disposition:s:{index}"), if track.default { "default" } else { "0" }.into(),
+        ]);
+        inputs.push(path.clone());
+    }
+    if !tracks.is_empty() {
+        // MP4 only takes timed text, so SRT and ASS lose their styling there
+        let subtitle_codec = if ext == "mp4" { "mov_text" } else { "copy" };
+        args.extend(["-c:s".into(), subtitle_codec.into()]);
+    }
+    args.extend(["-c:v".into(), "copy".into(), "-c:a".into(), codec.into()]);
+    let mut filters = Vec::new();
+    if let Some((track, path)) = burned.first() {
+        let filter = match track.kind {
+            SubtitleKind::Srt => "subtitles",
+            SubtitleKind::Danmaku => "ass",
+        };
+        filters.push(format!("{filter}={}", filter_path(path)));
+    }
+    let profile = info.info.profile.clone().or_else(|| (!filters.is_empty()).then(|| {
+        // Burning on its own encodes with the burn settings and leaves the audio alone
+        let burn = CONFIG.read().unwrap().burn.clone();
+        TranscodeProfile {
+            name: String::new(),
+            video_codec: VideoCodec::H264,
+            crf: burn.crf,
+            bitrate: 0,
+            preset: burn.preset,
+            max_height: 0,
+            audio_codec: AudioCodec::Copy,
+            audio_bitrate: 0,
+        }
+    }));
+    if let Some(profile) = &profile {
+        args.extend(encode_args(profile, filters, ext));
+    }
+    args.extend(metadata_args(&info.info));
+    // The attached picture is a single frame and would cut the output short
+    if !(ext == "mp4" && cover.is_some()) {
+        args.push("-shortest".into());
+    }
+    args.extend([
+        output.to_string_lossy().into(), "-progress".into(),
+        progress_path_clone.to_string_lossy().into(), "-y".into(),
+    ]);
+    let args: Vec<String> = inputs.iter()
+        .flat_map(|v| ["-i".into(), v.to_string_lossy().into()])
+        .chain(args).collect();
     let ffmpeg = async {
-        let result = app.shell().sidecar("ffmpeg")?
-            .args([
-                "-i", paths[0].clone().unwrap().to_str().unwrap(),
-                "-i", paths[1].clone().unwrap().to_str().unwrap(),
-                "-c:v", "copy",
-                "-c:a", codec,
-                "-shortest",
-                &output.to_str().unwrap(), "-progress",
-                progress_path_clone.to_str().unwrap(), "-y"
-            ]).output().await?;
+        let result = run(args, token).await?;
 
         log::info!("STDOUT: {:?}", String::from_utf8_lossy(&result.stdout));
         log::info!("STDERR: {:?}", String::from_utf8_lossy(&result.stderr));
-        if result.status.success() {
+        if result.success() {
             Ok::<(), anyhow::Error>(())
         } else {
-            Err(anyhow!("FFmpeg exited with status: {}", result.status.code().unwrap_or(-1)))
+            Err(anyhow!("FFmpeg exited with status: {}", result.code.unwrap_or(-1)))
         }
     };
     let monitor = async {
-        let task = info.tasks.iter().find(|v| v.task_type == TaskType::Video).unwrap();
+        let task = info.tasks.iter().find(|v| v.task_type == TaskType::Merge)
+            .or_else(|| info.tasks.iter().find(|v| v.task_type == TaskType::Video)).unwrap();
         monitor(info.id.clone(), task, progress_path, stream_info.0, event).await?;
         Ok::<(), anyhow::Error>(())
     };
     let result = tokio::try_join!(ffmpeg, monitor);
     result.context("Failed to merge")?;
+    if chapters.is_some() && chapter_mode == ChapterMode::Split {
+        split_chapters(&info, token).await.context("Failed to split chapters")?;
+    }
     Ok(())
 }
 
-pub async fn raw_flac(info: Arc<QueueInfo>) -> Result<()> {
+pub async fn decode_check(path: &Path, token: &CancelToken) -> Result<()> {
+    let result = run([
+        "-v", "error",
+        "-i", path.to_str().unwrap(),
+        "-f", "null", "-",
+    ], token).await?;
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    if result.success() && stderr.trim().is_empty() {
+        return Ok(());
+    }
+    // Corrupt streams can report an error for every frame
+    let errors = stderr.lines().take(5).collect::<Vec<_>>().join("\n");
+    Err(anyhow!("{} failed to decode (status {}): {errors}", path.display(), result.code.unwrap_or(-1)))
+}
+
+pub async fn raw_flac(info: Arc<QueueInfo>, token: &CancelToken) -> Result<()> {
     let output = info.output.clone();
     let input_path = info.tasks.iter()
         .find(|task| task.task_type == TaskType::Audio)
         .map(|task| task.path.clone()).unwrap();
 
-    let app = get_app_handle();
-    let status = app.shell().sidecar("ffmpeg")?
-        .args([
-            "-i", input_path.unwrap().to_str().unwrap(),
-            "-vn", "-acodec", "flac",
-            &output.to_str().unwrap()
-        ])
-        .status().await?;
+    let mut args: Vec<String> = vec!["-i".into(), input_path.unwrap().to_string_lossy().into()];
+    match fetch_cover(&info).await {
+        Some(cover) => args.extend([
+            "-i".into(), cover.to_string_lossy().into(),
+            "-map".into(), "0:a:0".into(), "-map".into(), "1:v:0".into(),
+            "-c:v".into(), "copy".into(), "-disposition:v".into(), "attached_pic".into(),
+        ]),
+        None => args.push("-vn".into()),
+    }
+    args.extend(["-acodec".into(), "flac".into()]);
+    args.extend(metadata_args(&info.info));
+    args.push(output.to_string_lossy().into());
+    let status = run(args, token).await?;
 
     if status.success() {
         Ok(())
     } else {
-        Err(anyhow!("FFmpeg exited with status: {}", status.code().unwrap_or(-1)))
+        Err(anyhow!("FFmpeg exited with status: {}", status.code.unwrap_or(-1)))
     }
 }
 
-async fn monitor(id: String, task: &Task, progress_path: PathBuf, frames: u64, event: &Channel<DownloadEvent>) -> Result<()> {
-    while !progress_path.exists() {
-        sleep(Duration::from_millis(250)).await;
+fn metadata_args(info: &ArchiveInfo) -> Vec<String> {
+    let mut tags = vec![("title", Some(info.title.clone()))];
+    if let Some(meta) = &info.metadata {
+        let comment = [&meta.description, &meta.url].into_iter().flatten()
+            .filter(|v| !v.is_empty()).cloned().collect::<Vec<_>>().join("\n\n");
+        tags.extend([
+            ("artist", meta.artist.clone()),
+            ("date", meta.date.clone()),
+            ("comment", Some(comment).filter(|v| !v.is_empty())),
+            ("album", meta.album.clone()),
+            ("genre", meta.genre.clone()),
+            ("track", meta.episode.map(|v| v.to_string())),
+        ]);
+    }
+    tags.into_iter()
+        .filter_map(|(key, value)| Some(["-metadata".into(), format!("{key}={}", value?)]))
+        .flatten().collect()
+}
+
+fn work_dir(info: &QueueInfo) -> Option<&Path> {
+    info.tasks.iter().find_map(|v| v.path.as_deref()?.parent())
+}
+
+async fn write_chapters(info: &QueueInfo) -> Option<PathBuf> {
+    let chapters = &info.info.chapters;
+    if chapters.is_empty() {
+        return None;
+    }
+    let escape = |v: &str| v.chars().fold(String::new(), |mut s, c| {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            s.push('\\');
+        }
+        s.push(c);
+        s
+    });
+    let mut content = String::from(";FFMETADATA1\n");
+    for chapter in chapters {
+        content += &format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            chapter.start * 1000, chapter.end * 1000, escape(&chapter.title)
+        );
+    }
+    let path = work_dir(info)?.join("chapters.txt");
+    fs::write(&path, content).await
+        .map_err(|e| log::warn!("Failed to write chapters for {}: {e}", info.id)).ok()?;
+    Some(path)
+}
+
+async fn split_chapters(info: &QueueInfo, token: &CancelToken) -> Result<()> {
+    let output = &info.output;
+    let ext = output.extension().and_then(|v| v.to_str()).unwrap_or_default();
+    let dir = output.with_extension("");
+    fs::create_dir_all(&dir).await?;
+    for (index, chapter) in info.info.chapters.iter().enumerate() {
+        let title = chapter.title.replace(['\\', '/', ':', '*', '?', '"', '<', '>', '|'], "_");
+        let path = dir.join(format!("{:02} - {title}.{ext}", index + 1));
+        let result = run([
+            "-ss", &chapter.start.to_string(),
+            "-t", &(chapter.end - chapter.start).to_string(),
+            "-i", output.to_str().unwrap(),
+            "-map", "0", "-c", "copy", "-map_chapters", "-1",
+            "-metadata", &format!("title={}", chapter.title),
+            "-avoid_negative_ts", "make_zero",
+            path.to_str().unwrap(), "-y",
+        ], token).await?;
+        if !result.success() {
+            return Err(anyhow!("FFmpeg exited with status: {}", result.code.unwrap_or(-1)));
+        }
     }
+    Ok(())
+}
+
+async fn subtitle_tracks<'a>(info: &'a QueueInfo, token: &CancelToken) -> Vec<(&'a SubtitleTrack, PathBuf)> {
+    let mut tracks = Vec::new();
+    for track in &info.info.subtitles {
+        let Some(path) = &track.path else { continue };
+        let path = match track.kind {
+            SubtitleKind::Srt => path.clone(),
+            SubtitleKind::Danmaku => match danmaku_to_ass(path, token).await {
+                Ok(path) => path,
+                Err(e) => {
+                    log::warn!("Failed to convert danmaku for {}: {e:#}", info.id);
+                    continue;
+                }
+            },
+        };
+        tracks.push((track, path));
+    }
+    tracks
+}
+
+// Only the main video is re-encoded, an attached cover stays as it is
+fn encode_args(profile: &TranscodeProfile, mut filters: Vec<String>, ext: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let encoder = match profile.video_codec {
+        VideoCodec::Copy if filters.is_empty() => None,
+        // FLV has no HEVC mapping either
+        VideoCodec::Hevc if ext != "flv" => Some("libx265"),
+        _ => Some("libx264"),
+    };
+    if let Some(encoder) = encoder {
+        if profile.max_height > 0 {
+            // A width of -2 keeps the aspect ratio at an even size, as both encoders require
+            filters.push(format!("scale=-2:'min(ih,{})'", profile.max_height));
+        }
+        if !filters.is_empty() {
+            args.extend(["-filter:v:0".into(), filters.join(",")]);
+        }
+        args.extend([
+            "-c:v:0".into(), encoder.into(),
+            "-preset".into(), profile.preset.clone(),
+            "-pix_fmt:v:0".into(), "yuv420p".into(),
+        ]);
+        match profile.bitrate {
+            0 => args.extend(["-crf".into(), profile.crf.to_string()]),
+            bitrate => args.extend(["-b:v:0".into(), format!("{bitrate}k")]),
+        }
+        if encoder == "libx265" && ext == "mp4" {
+            // Apple players only accept HEVC in MP4 when it is tagged as hvc1
+            args.extend(["-tag:v:0".into(), "hvc1".into()]);
+        }
+    }
+    let audio = match profile.audio_codec {
+        AudioCodec::Copy => None,
+        // FLV has no Opus mapping
+        AudioCodec::Opus if ext != "flv" => Some("libopus"),
+        _ => Some("aac"),
+    };
+    if let Some(audio) = audio {
+        args.extend(["-c:a".into(), audio.into()]);
+        if profile.audio_bitrate > 0 {
+            args.extend(["-b:a".into(), format!("{}k", profile.audio_bitrate)]);
+        }
+    }
+    args
+}
+
+pub async fn convert(id: Arc<String>, input: &Path, output: &Path, profile: &TranscodeProfile, token: &CancelToken) -> Result<()> {
+    let ext = output.extension().and_then(|v| v.to_str()).unwrap_or_default();
+    let probe = probe(input, token).await.context("Failed to get stream info")?;
+    let profile = &match probe.frames {
+        Some(_) => profile.clone(),
+        // Without a main video only the audio settings apply, an attached cover is kept as it is
+        None => TranscodeProfile { video_codec: VideoCodec::Copy, max_height: 0, ..profile.clone() },
+    };
+    if ext == "flv" && profile.video_codec == VideoCodec::Copy && probe.video_codec.as_deref() == Some("hevc") {
+        return Err(anyhow!("FLV can't hold HEVC video, pick a profile that re-encodes it"));
+    }
+    let progress_path = get_app_handle().path().app_log_dir()?
+        .join("ffmpeg").join(format!("{id}_{}.log", get_ts(true)));
+    fs::create_dir_all(progress_path.parent().unwrap()).await
+        .context("Failed to create FFmpeg progress Folder")?;
+    let mut args: Vec<String> = vec![
+        "-i".into(), input.to_string_lossy().into(),
+        "-map".into(), "0".into(), "-c".into(), "copy".into(),
+    ];
+    args.extend(encode_args(profile, Vec::new(), ext));
+    args.extend([
+        output.to_string_lossy().into(), "-progress".into(),
+        progress_path.to_string_lossy().into(), "-y".into(),
+    ]);
+    let ffmpeg = async {
+        let result = run(args, token).await?;
+        log::info!("STDERR: {:?}", String::from_utf8_lossy(&result.stderr));
+        if result.success() {
+            Ok::<(), anyhow::Error>(())
+        } else {
+            Err(anyhow!("FFmpeg exited with status: {}", result.code.unwrap_or(-1)))
+        }
+    };
+    // Audio has no frames to count, so its progress is measured in time
+    let monitor = watch_progress(progress_path, || (), |log_data| {
+        let (content_length, chunk_length) = match probe.frames {
+            Some(frames) => (frames, log_data.frame),
+            None => (probe.duration_ms, log_data.out_time_us / 1000),
+        };
+        DOWNLOAD_EVENTS.send(DownloadEvent::Converting {
+            id: id.clone(), content_length, chunk_length,
+        });
+    });
+    tokio::try_join!(ffmpeg, monitor)?;
+    Ok(())
+}
+
+// Paths are escaped for the filter options first, then for the filtergraph around them
+fn filter_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().replace('\\', "/").chars() {
+        match c {
+            ':' => escaped.push_str("\\\\:"),
+            '\'' => escaped.push_str("\\\\\\'"),
+            ',' | ';' | '[' | ']' => escaped.extend(['\\', c]),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+async fn danmaku_to_ass(input: &Path, token: &CancelToken) -> Result<PathBuf> {
+    let output = input.with_extension("ass");
+    let result = run_sidecar("DanmakuFactory", [
+        "-i", input.to_str().unwrap(), "-o", output.to_str().unwrap(),
+    ], token).await?;
+    if !fs::try_exists(&output).await? {
+        return Err(anyhow!("DanmakuFactory produced no output: {}", String::from_utf8_lossy(&result.stderr)));
+    }
+    Ok(output)
+}
+
+async fn fetch_cover(info: &QueueInfo) -> Option<PathBuf> {
+    let dir = work_dir(info)?;
+    if info.info.cover.is_empty() {
+        return None;
+    }
+    let result = async {
+        let client = init_client().await?;
+        let bytes = client.get(&info.info.cover).send().await?.error_for_status()?.bytes().await?;
+        // Containers only take JPEG or PNG pictures
+        let ext = match bytes.get(..4) {
+            Some([0xFF, 0xD8, _, _]) => "jpg",
+            Some([0x89, b'P', b'N', b'G']) => "png",
+            _ => return Err(anyhow!("Unsupported cover format")),
+        };
+        let path = dir.join(format!("cover.{ext}"));
+        fs::write(&path, &bytes).await?;
+        Ok::<PathBuf, anyhow::Error>(path)
+    }.await;
+    result.map_err(|e| log::warn!("Failed to fetch cover for {}: {e:#}", info.id)).ok()
+}
+
+fn cover_mime(path: &Path) -> &'static str {
+    match path.extension().and_then(|v| v.to_str()) {
+        Some("png") => "image/png",
+        _ => "image/jpeg",
+    }
+}
+
+async fn monitor(id: String, task: &Task, progress_path: PathBuf, frames: u64, event: &DownloadChannels) -> Result<()> {
     let gid = Arc::new(task.gid.as_ref().unwrap_or(&String::new()).clone());
     let id = Arc::new(id);
-    event.send(DownloadEvent::Started { id: id.clone(), gid, task_type: TaskType::Merge })?;
+    watch_progress(progress_path, || {
+        event.send(DownloadEvent::Started { id: id.clone(), gid: gid.clone(), task_type: TaskType::Merge });
+    }, |log_data| {
+        // Merge progress is counted in frames, so speed is reported as fps
+        send_progress(
+            id.clone(), gid.clone(),
+            frames, log_data.frame, log_data.fps as u64, 0,
+        );
+    }).await?;
+    event.send(DownloadEvent::Finished { id, gid });
+    Ok(())
+}
+
+async fn watch_progress(
+    progress_path: PathBuf,
+    on_start: impl FnOnce(),
+    on_progress: impl Fn(&FFmpegLog),
+) -> Result<()> {
+    while !progress_path.exists() {
+        sleep(Duration::from_millis(250)).await;
+    }
+    on_start();
     let mut last_size = 0u64;
     let mut map = Map::new();
     let mut keys = Vec::new();
     loop {
-        let gid = Arc::new(task.gid.as_ref().unwrap_or(&String::new()).clone());
         let metadata = fs::metadata(&progress_path)
             .await.with_context(||
                 format!("Failed to get FFmpeg progress metadata: {}", &progress_path.to_string_lossy())
@@ -204,22 +647,9 @@ async fn monitor(id: String, task: &Task, progress_path: PathBuf, frames: u64, e
         let log_data: FFmpegLog = serde_json::from_value(Value::Object(map.clone()))
             .context("Failed to parse FFmpeg Log")?;
         match log_data.progress.as_str() {
-            "continue" => {
-                event.send(DownloadEvent::Progress {
-                    id: id.clone(),
-                    gid: gid.clone(),
-                    content_length: frames,
-                    chunk_length: log_data.frame,
-                })?;
-            },
-            "end" => {
-                event.send(DownloadEvent::Finished {
-                    id: id.clone(),
-                    gid: gid.clone(),
-                })?;
-                return Ok(());
-            }
-            _ => {},
+            "continue" => on_progress(&log_data),
+            "end" => return Ok(()),
+            _ => (),
         }
         sleep(Duration::from_millis(200)).await;
     }
@@ -1,12 +1,27 @@
 pub mod aria2c;
+pub mod disk;
+pub mod downloader;
 pub mod ffmpeg;
 pub mod login;
+pub mod native;
+pub mod temp;
 
-use crate::{config, TauriResult, shared::SECRET};
+use crate::{config, TauriResult, shared::{kill_children, reap_orphans, SECRET}};
 
 pub async fn init() -> TauriResult<()> {
     let secret = SECRET.read().unwrap().clone();
     config::rw_config("init", None, secret).await?;
+    // A second aria2c would otherwise fight a leftover one over the session and files
+    reap_orphans();
     aria2c::init()?;
+    tauri::async_runtime::spawn(aria2c::scheduler());
+    aria2c::restore().await?;
+    // Orphans can only be told apart once every queued item is known
+    tauri::async_runtime::spawn(temp::sweep_temp());
     Ok(())
-}
\ No newline at end of file
+}
+
+pub async fn shutdown() {
+    aria2c::shutdown().await;
+    kill_children();
+}
@@ -0,0 +1,432 @@
+use std::{collections::HashMap, ffi::OsString, io::SeekFrom, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex as StdMutex, RwLock as StdRwLock}, time::{Duration, Instant}};
+use tokio::{fs::{self, OpenOptions}, io::{AsyncSeekExt, AsyncWriteExt}, sync::Mutex, time::sleep};
+use tauri::async_runtime::{self, JoinHandle};
+use tauri_plugin_http::reqwest::{header, Client, StatusCode};
+use futures_util::{future::try_join_all, StreamExt};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use lazy_static::lazy_static;
+
+use crate::{shared::init_client, TauriResult};
+use super::downloader::{notify, DownloadState, DownloadStatus, Downloader};
+
+pub const CONTROL_EXT: &str = "bilitools";
+const CONNECTIONS: u64 = 4;
+const MIN_SEGMENT: u64 = 1 << 20;
+// Kept small, failed items are retried as a whole on top of this
+const SEGMENT_RETRY: usize = 3;
+
+lazy_static! {
+    static ref TASKS: StdRwLock<HashMap<String, Arc<NativeTask>>> = StdRwLock::new(HashMap::new());
+    static ref GLOBAL_LIMIT: RateLimiter = RateLimiter::new(0);
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Control {
+    total: u64,
+    ranged: bool,
+    segments: Vec<(u64, u64, u64)>,
+}
+
+#[derive(Debug)]
+struct Segment {
+    start: u64,
+    end: u64,
+    done: AtomicU64,
+}
+
+impl Segment {
+    fn remaining(&self) -> u64 {
+        (self.end - self.start).saturating_sub(self.done.load(Ordering::Relaxed))
+    }
+}
+
+struct RateLimiter {
+    limit: AtomicU64,
+    window: Mutex<(Instant, u64)>,
+}
+
+impl RateLimiter {
+    fn new(limit: u64) -> Self {
+        Self { limit: AtomicU64::new(limit), window: Mutex::new((Instant::now(), 0)) }
+    }
+    fn set(&self, limit: u64) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+    async fn acquire(&self, len: u64) {
+        let limit = self.limit.load(Ordering::Relaxed);
+        if limit == 0 {
+            return;
+        }
+        let delay = {
+            let mut window = self.window.lock().await;
+            if window.0.elapsed() >= Duration::from_secs(1) {
+                *window = (Instant::now(), 0);
+            }
+            window.1 += len;
+            Duration::from_secs_f64(window.1 as f64 / limit as f64)
+                .saturating_sub(window.0.elapsed())
+        };
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+    }
+}
+
+struct NativeTask {
+    gid: String,
+    urls: Vec<String>,
+    path: PathBuf,
+    status: StdRwLock<(DownloadState, Option<(isize, String)>)>,
+    segments: StdRwLock<Arc<Vec<Segment>>>,
+    total: AtomicU64,
+    speed: AtomicU64,
+    connections: AtomicU64,
+    limiter: RateLimiter,
+    handle: StdMutex<Option<JoinHandle<()>>>,
+}
+
+impl NativeTask {
+    fn completed(&self) -> u64 {
+        self.segments.read().unwrap().iter().map(|v| v.done.load(Ordering::Relaxed)).sum()
+    }
+    fn set_status(&self, status: DownloadState, error: Option<(isize, String)>) {
+        *self.status.write().unwrap() = (status, error);
+    }
+    fn to_status(&self) -> DownloadStatus {
+        let (state, error) = self.status.read().unwrap().clone();
+        let completed = self.completed();
+        DownloadStatus {
+            gid: self.gid.clone(),
+            state,
+            total_length: self.total.load(Ordering::Relaxed).max(completed),
+            completed_length: completed,
+            download_speed: self.speed.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+            error_code: error.as_ref().map(|v| v.0),
+            error_message: error.map(|v| v.1),
+        }
+    }
+    fn abort(&self) {
+        if let Some(handle) = self.handle.lock().unwrap().take() {
+            handle.abort();
+        }
+        self.speed.store(0, Ordering::Relaxed);
+        self.connections.store(0, Ordering::Relaxed);
+    }
+}
+
+pub fn control_path(path: &Path) -> PathBuf {
+    let mut control = OsString::from(path.as_os_str());
+    control.push(format!(".{CONTROL_EXT}"));
+    PathBuf::from(control)
+}
+
+fn get(gid: &str) -> Result<Arc<NativeTask>> {
+    TASKS.read().unwrap().get(gid).cloned()
+        .ok_or(anyhow!("GID {gid} is not found"))
+}
+
+fn add(urls: &[String], path: &Path, gid: Option<&str>, pause: bool, limit: Option<u64>) -> String {
+    let gid = gid.map(String::from).unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+    let task = Arc::new(NativeTask {
+        gid: gid.clone(),
+        urls: urls.to_vec(),
+        path: path.to_path_buf(),
+        status: StdRwLock::new((DownloadState::Waiting, None)),
+        segments: StdRwLock::new(Arc::new(Vec::new())),
+        total: AtomicU64::new(0),
+        speed: AtomicU64::new(0),
+        connections: AtomicU64::new(0),
+        limiter: RateLimiter::new(limit.unwrap_or(0)),
+        handle: StdMutex::new(None),
+    });
+    if let Some(old) = TASKS.write().unwrap().insert(gid.clone(), task.clone()) {
+        old.abort();
+    }
+    if pause {
+        task.set_status(DownloadState::Paused, None);
+    } else {
+        start(&task);
+    }
+    gid
+}
+
+fn start(task: &Arc<NativeTask>) {
+    task.set_status(DownloadState::Active, None);
+    let task_cloned = task.clone();
+    let handle = async_runtime::spawn(async move {
+        let result = download(&task_cloned).await;
+        task_cloned.speed.store(0, Ordering::Relaxed);
+        task_cloned.connections.store(0, Ordering::Relaxed);
+        match result {
+            Ok(()) => {
+                task_cloned.set_status(DownloadState::Complete, None);
+                notify(&task_cloned.gid, DownloadState::Complete);
+            },
+            Err(e) => {
+                log::warn!("Native download {} failed: {e:#}", task_cloned.gid);
+                task_cloned.set_status(DownloadState::Error, Some((1, format!("{e:#}"))));
+                notify(&task_cloned.gid, DownloadState::Error);
+            }
+        }
+    });
+    *task.handle.lock().unwrap() = Some(handle);
+}
+
+async fn pause(gid: &str) -> Result<()> {
+    let task = get(gid)?;
+    let status = task.status.read().unwrap().0;
+    if matches!(status, DownloadState::Active | DownloadState::Waiting) {
+        task.abort();
+        task.set_status(DownloadState::Paused, None);
+        save_control(&task).await?;
+        notify(gid, DownloadState::Paused);
+    }
+    Ok(())
+}
+
+fn resume(gid: &str) -> Result<()> {
+    let task = get(gid)?;
+    let status = task.status.read().unwrap().0;
+    if matches!(status, DownloadState::Paused | DownloadState::Waiting) {
+        start(&task);
+    }
+    Ok(())
+}
+
+async fn remove(gid: &str) -> Result<()> {
+    let task = get(gid)?;
+    let status = task.status.read().unwrap().0;
+    task.abort();
+    if status == DownloadState::Active {
+        save_control(&task).await?;
+    }
+    task.set_status(DownloadState::Removed, None);
+    notify(gid, DownloadState::Removed);
+    Ok(())
+}
+
+fn forget(gid: &str) -> Result<()> {
+    get(gid)?.abort();
+    TASKS.write().unwrap().remove(gid);
+    Ok(())
+}
+
+pub struct Native;
+
+impl Downloader for Native {
+    async fn add(&self, urls: &[String], path: &Path, gid: Option<&str>, pause: bool, limit: Option<u64>) -> TauriResult<String> {
+        Ok(add(urls, path, gid, pause, limit))
+    }
+    async fn pause(&self, gid: &str) -> TauriResult<()> {
+        Ok(pause(gid).await?)
+    }
+    async fn resume(&self, gid: &str) -> TauriResult<()> {
+        Ok(resume(gid)?)
+    }
+    async fn remove(&self, gid: &str) -> TauriResult<()> {
+        Ok(remove(gid).await?)
+    }
+    async fn forget(&self, gid: &str) -> TauriResult<()> {
+        Ok(forget(gid)?)
+    }
+    async fn status(&self, gid: &str) -> TauriResult<Option<DownloadStatus>> {
+        Ok(TASKS.read().unwrap().get(gid).map(|v| v.to_status()))
+    }
+    async fn active(&self) -> TauriResult<Vec<DownloadStatus>> {
+        Ok(TASKS.read().unwrap().values()
+            .filter(|v| v.status.read().unwrap().0 == DownloadState::Active)
+            .map(|v| v.to_status()).collect())
+    }
+    async fn set_limit(&self, gid: &str, limit: Option<u64>) -> TauriResult<()> {
+        get(gid)?.limiter.set(limit.unwrap_or(0));
+        Ok(())
+    }
+}
+
+pub async fn shutdown() {
+    let active: Vec<String> = TASKS.read().unwrap().values()
+        .filter(|v| v.status.read().unwrap().0 == DownloadState::Active)
+        .map(|v| v.gid.clone()).collect();
+    for gid in active {
+        if let Err(e) = pause(&gid).await {
+            log::warn!("Failed to pause native download {gid}: {e:#}");
+        }
+    }
+}
+
+pub fn set_global_limit(limit: u64) {
+    GLOBAL_LIMIT.set(limit);
+}
+
+async fn save_control(task: &NativeTask) -> Result<()> {
+    let segments = task.segments.read().unwrap().clone();
+    if segments.is_empty() {
+        return Ok(());
+    }
+    let control = Control {
+        total: task.total.load(Ordering::Relaxed),
+        ranged: segments.len() > 1 || segments[0].end != u64::MAX,
+        segments: segments.iter().map(|v| (v.start, v.end, v.done.load(Ordering::Relaxed))).collect(),
+    };
+    fs::write(control_path(&task.path), serde_json::to_vec(&control)?).await
+        .context("Failed to save download control file")
+}
+
+async fn load_control(task: &NativeTask) -> Option<Control> {
+    if !task.path.exists() {
+        return None;
+    }
+    let data = fs::read(control_path(&task.path)).await.ok()?;
+    serde_json::from_slice::<Control>(&data).ok().filter(|v| v.ranged)
+}
+
+async fn probe(client: &Client, url: &str) -> Result<(u64, bool)> {
+    let response = client.get(url)
+        .header(header::RANGE, "bytes=0-0")
+        .send().await?;
+    let status = response.status();
+    if status == StatusCode::PARTIAL_CONTENT {
+        let total = response.headers().get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit('/').next())
+            .and_then(|v| v.parse::<u64>().ok());
+        if let Some(total) = total {
+            return Ok((total, true));
+        }
+    }
+    if !status.is_success() {
+        bail!("Unexpected response status {status} from {url}");
+    }
+    Ok((response.content_length().unwrap_or(0), false))
+}
+
+async fn download(task: &Arc<NativeTask>) -> Result<()> {
+    let client = init_client().await?;
+    let (control, fresh) = match load_control(task).await {
+        Some(control) => (control, false),
+        None => {
+            let (total, ranged) = probe(&client, &task.urls[0]).await?;
+            let segments = if ranged && total > 0 {
+                let count = CONNECTIONS.min(total / MIN_SEGMENT).max(1);
+                let size = total.div_ceil(count);
+                (0..count).map(|i| (i * size, ((i + 1) * size).min(total), 0)).collect()
+            } else {
+                vec![(0, u64::MAX, 0)]
+            };
+            if let Some(parent) = task.path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            (Control { total, ranged, segments }, true)
+        }
+    };
+    task.total.store(control.total, Ordering::Relaxed);
+    let segments = Arc::new(control.segments.iter().map(|&(start, end, done)|
+        Segment { start, end, done: AtomicU64::new(done) }
+    ).collect::<Vec<_>>());
+    *task.segments.write().unwrap() = segments.clone();
+    // The control file comes first, a preallocated file without one would pass as complete
+    save_control(task).await?;
+    if fresh {
+        let file = OpenOptions::new().create(true).write(true).truncate(true)
+            .open(&task.path).await.context("Failed to create output file")?;
+        if control.ranged {
+            file.set_len(control.total).await?;
+        }
+    }
+
+    let workers = try_join_all((0..segments.len()).map(|i|
+        fetch_segment(task.clone(), client.clone(), segments.clone(), i, control.ranged)
+    ));
+    tokio::pin!(workers);
+    let mut last = task.completed();
+    loop {
+        tokio::select! {
+            result = &mut workers => { result?; break; },
+            _ = sleep(Duration::from_secs(1)) => {
+                let completed = task.completed();
+                task.speed.store(completed.saturating_sub(last), Ordering::Relaxed);
+                last = completed;
+                if let Err(e) = save_control(task).await {
+                    log::warn!("{e:#}");
+                }
+            }
+        }
+    }
+    let completed = task.completed();
+    if control.ranged && completed != control.total {
+        bail!("Downloaded size {completed} does not match expected size {}", control.total);
+    }
+    task.total.store(completed, Ordering::Relaxed);
+    let _ = fs::remove_file(control_path(&task.path)).await;
+    Ok(())
+}
+
+async fn fetch_segment(
+    task: Arc<NativeTask>,
+    client: Client,
+    segments: Arc<Vec<Segment>>,
+    index: usize,
+    ranged: bool,
+) -> Result<()> {
+    let segment = &segments[index];
+    let mut attempt = 0;
+    while segment.remaining() > 0 {
+        // Each attempt starts from a different mirror
+        let url = &task.urls[(index + attempt) % task.urls.len()];
+        task.connections.fetch_add(1, Ordering::Relaxed);
+        let result = fetch_range(&task, &client, segment, ranged, url).await;
+        task.connections.fetch_sub(1, Ordering::Relaxed);
+        match result {
+            Ok(()) => break,
+            Err(e) if attempt < SEGMENT_RETRY => {
+                attempt += 1;
+                log::warn!("Segment {index} of {} failed, retrying ({attempt}/{SEGMENT_RETRY}): {e:#}", task.gid);
+                if !ranged {
+                    segment.done.store(0, Ordering::Relaxed);
+                }
+                sleep(Duration::from_secs(1u64 << (attempt - 1).min(6))).await;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+async fn fetch_range(task: &NativeTask, client: &Client, segment: &Segment, ranged: bool, url: &str) -> Result<()> {
+    let offset = segment.start + segment.done.load(Ordering::Relaxed);
+    let mut request = client.get(url);
+    if ranged {
+        request = request.header(header::RANGE, format!("bytes={}-{}", offset, segment.end - 1));
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    if (ranged && status != StatusCode::PARTIAL_CONTENT) || !status.is_success() {
+        bail!("Unexpected response status {status} from {url}");
+    }
+    let mut file = OpenOptions::new().write(true).open(&task.path).await
+        .context("Failed to open output file")?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        let len = (chunk.len() as u64).min(segment.remaining()) as usize;
+        task.limiter.acquire(len as u64).await;
+        GLOBAL_LIMIT.acquire(len as u64).await;
+        file.write_all(&chunk[..len]).await?;
+        segment.done.fetch_add(len as u64, Ordering::Relaxed);
+        if segment.remaining() == 0 {
+            break;
+        }
+    }
+    file.flush().await?;
+    if !ranged {
+        // The whole body was received, so the segment is done regardless of the advertised size
+        return Ok(());
+    }
+    if segment.remaining() > 0 {
+        bail!("Connection closed with {} bytes remaining", segment.remaining());
+    }
+    Ok(())
+}
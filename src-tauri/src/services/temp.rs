@@ -0,0 +1,136 @@
+use std::{collections::HashSet, fs, path::PathBuf, time::{Duration, SystemTime}};
+use tauri::async_runtime;
+use anyhow::Result;
+use serde::Serialize;
+use specta::Type;
+
+use super::aria2c::{is_local, QueueInfo, QueueType, QUEUE_MANAGER};
+use crate::{config::TempGcMode, shared::CONFIG, TauriResult};
+
+pub fn temp_root() -> PathBuf {
+    CONFIG.read().unwrap().temp_dir.join("com.btjawa.bilitools")
+}
+
+#[derive(Clone, Debug, Serialize, Type)]
+pub struct TempOrphan {
+    pub path: PathBuf,
+    pub size: u64,
+    pub age: u64,
+}
+
+async fn find_orphans() -> Result<Vec<TempOrphan>> {
+    let min_age = Duration::from_secs(CONFIG.read().unwrap().temp_gc.age * 3600);
+    let mut known = HashSet::new();
+    for queue_type in [QueueType::Waiting, QueueType::Doing, QueueType::Paused, QueueType::Failed] {
+        for info in QUEUE_MANAGER.get(queue_type).await.iter() {
+            known.extend(info.tasks.iter().filter_map(|v| Some(v.path.as_deref()?.parent()?.to_path_buf())));
+        }
+    }
+    let root = temp_root();
+    async_runtime::spawn_blocking(move || -> Result<Vec<TempOrphan>> {
+        let mut orphans = Vec::new();
+        if !root.exists() {
+            return Ok(orphans);
+        }
+        let now = SystemTime::now();
+        for entry in fs::read_dir(&root)? {
+            let path = entry?.path();
+            if known.contains(&path) {
+                continue;
+            }
+            // A folder counts as touched whenever anything inside it was
+            let (mut size, mut modified) = (0, SystemTime::UNIX_EPOCH);
+            for entry in walkdir::WalkDir::new(&path).into_iter().filter_map(Result::ok) {
+                let Ok(meta) = entry.metadata() else { continue };
+                if meta.is_file() {
+                    size += meta.len();
+                }
+                if let Ok(time) = meta.modified() {
+                    modified = modified.max(time);
+                }
+            }
+            let age = now.duration_since(modified).unwrap_or_default();
+            if age >= min_age {
+                orphans.push(TempOrphan { path, size, age: age.as_secs() });
+            }
+        }
+        Ok(orphans)
+    }).await?
+}
+
+fn remove_orphan(orphan: &TempOrphan) -> std::io::Result<()> {
+    if orphan.path.is_dir() {
+        fs::remove_dir_all(&orphan.path)
+    } else {
+        fs::remove_file(&orphan.path)
+    }
+}
+
+pub async fn sweep_temp() {
+    let mode = CONFIG.read().unwrap().temp_gc.mode;
+    if mode == TempGcMode::Off {
+        return;
+    }
+    let orphans = match find_orphans().await {
+        Ok(orphans) => orphans,
+        Err(e) => {
+            log::warn!("Failed to scan temp files: {e:#}");
+            return;
+        }
+    };
+    let mut freed = 0;
+    for orphan in &orphans {
+        if mode == TempGcMode::Report {
+            log::info!("Orphaned temp file: {} ({} bytes)", orphan.path.display(), orphan.size);
+            continue;
+        }
+        match remove_orphan(orphan) {
+            Ok(_) => freed += orphan.size,
+            Err(e) => log::warn!("Failed to remove {}: {e}", orphan.path.display()),
+        }
+    }
+    if mode == TempGcMode::Delete && !orphans.is_empty() {
+        log::info!("Removed {} orphaned temp files, {freed} bytes freed", orphans.len());
+    }
+}
+
+#[tauri::command(async)]
+#[specta::specta]
+pub async fn clean_temp(dry_run: bool) -> TauriResult<Vec<TempOrphan>> {
+    let orphans = find_orphans().await?;
+    if !dry_run {
+        for orphan in &orphans {
+            remove_orphan(orphan)?;
+        }
+    }
+    Ok(orphans)
+}
+
+pub async fn cleanup(info: &QueueInfo) {
+    let paths = info.tasks.iter().filter_map(|v| v.path.as_deref()).filter(|v| is_local(v));
+    for dir in paths.filter_map(|v| v.parent()) {
+        if let Err(e) = fs::remove_dir_all(dir) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Failed to remove {}: {e}", dir.display());
+            }
+        }
+    }
+    // A merge or conversion may have started writing the output already
+    if let Err(e) = fs::remove_file(&info.output) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            log::warn!("Failed to remove {}: {e}", info.output.display());
+        }
+    }
+    if info.output.extension().is_some() {
+        let chapters = info.output.with_extension("");
+        if let Err(e) = fs::remove_dir_all(&chapters) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Failed to remove {}: {e}", chapters.display());
+            }
+        }
+    }
+    // The output folder may be shared with other items, so it only goes once it's empty
+    if let Some(parent) = info.output.parent() {
+        let _ = fs::remove_dir(parent);
+    }
+}
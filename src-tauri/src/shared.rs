@@ -1,5 +1,6 @@
 use tauri::{http::{HeaderMap, HeaderName, HeaderValue}, AppHandle, Manager, Wry};
-use std::{collections::BTreeMap, env, path::PathBuf, sync::{Arc, RwLock}};
+use std::{collections::{BTreeMap, HashMap}, env, path::{Path, PathBuf}, sync::{Arc, Mutex, RwLock}};
+use tauri_plugin_shell::process::CommandChild;
 use tauri_plugin_http::reqwest::{Client, Proxy};
 use rand::{distr::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
@@ -13,20 +14,98 @@ use chrono::Utc;
 
 use crate::{
     storage::config::{
+        DownloadBackend,
         Settings,
         SettingsAdvanced,
-        SettingsProxy
+        SettingsAria2,
+        SettingsAria2Rpc,
+        FileAllocation,
+        SettingsProxy,
+        SettingsSpeedLimit,
+        SettingsTempGc,
+        TempGcMode,
+        VerifyMode,
+        ChapterMode,
+        SettingsMux,
+        SettingsBurn,
+        BurnSource,
+        TranscodeProfile,
+        VideoCodec,
+        AudioCodec,
     },
     storage::cookies,
 };
 
 lazy_static! {
     pub static ref READY: Arc<RwLock<bool>> = Arc::new(RwLock::new(false));
+    pub static ref SHUTTING_DOWN: Arc<RwLock<bool>> = Arc::new(RwLock::new(false));
+    pub static ref CHILDREN: Arc<Mutex<HashMap<u32, (String, CommandChild)>>> = Arc::new(Mutex::new(HashMap::new()));
     pub static ref APP_HANDLE: Arc<OnceCell<AppHandle<Wry>>> = Arc::new(OnceCell::new());
     pub static ref CONFIG: Arc<RwLock<Settings>> = Arc::new(RwLock::new(Settings {
         temp_dir: env::temp_dir(),
+        temp_gc: SettingsTempGc {
+            mode: TempGcMode::Delete,
+            age: 7 * 24,
+        },
         down_dir: get_app_handle().path().desktop_dir().unwrap(),
+        min_free_space: 1024 * 1024 * 1024,
+        verify: VerifyMode::Size,
+        chapters: ChapterMode::Off,
+        mux: SettingsMux {
+            subtitles: false,
+            danmaku: false,
+            default: "none".into(),
+        },
+        burn: SettingsBurn {
+            source: BurnSource::Off,
+            crf: 23,
+            preset: "veryfast".into(),
+        },
+        profiles: vec![
+            TranscodeProfile {
+                name: "H.264 compatible 1080p".into(),
+                video_codec: VideoCodec::H264,
+                crf: 23,
+                bitrate: 0,
+                preset: "veryfast".into(),
+                max_height: 1080,
+                audio_codec: AudioCodec::Aac,
+                audio_bitrate: 192,
+            },
+            TranscodeProfile {
+                name: "Small HEVC archive".into(),
+                video_codec: VideoCodec::Hevc,
+                crf: 28,
+                bitrate: 0,
+                preset: "medium".into(),
+                max_height: 0,
+                audio_codec: AudioCodec::Opus,
+                audio_bitrate: 96,
+            },
+        ],
         max_conc: 3,
+        max_retry: 3,
+        download_backend: DownloadBackend::Aria2,
+        aria2_rpc: SettingsAria2Rpc {
+            external: false,
+            url: "http://127.0.0.1:6800/jsonrpc".into(),
+            secret: String::new(),
+            remote_dir: String::new(),
+            local_dir: String::new(),
+        },
+        aria2: SettingsAria2 {
+            split: 5,
+            max_connection_per_server: 1,
+            min_split_size: 20 * 1024 * 1024,
+            max_tries: 5,
+            file_allocation: FileAllocation::Prealloc,
+            disk_cache: 16 * 1024 * 1024,
+            lowest_speed_limit: 0,
+        },
+        speed_limit: SettingsSpeedLimit {
+            global: 0,
+            schedule: Vec::new(),
+        },
         df_dms: 80,
         df_ads: 30280,
         df_cdc: 7,
@@ -174,6 +253,193 @@ pub fn random_string(len: usize) -> String {
         .take(len).map(char::from).collect()
 }
 
+pub fn track_child(name: &str, child: CommandChild) -> u32 {
+    let pid = child.pid();
+    bind_child(pid);
+    let mut children = CHILDREN.lock().unwrap();
+    children.insert(pid, (name.into(), child));
+    record_children(&children);
+    pid
+}
+
+pub fn untrack_child(pid: u32) {
+    let mut children = CHILDREN.lock().unwrap();
+    children.remove(&pid);
+    record_children(&children);
+}
+
+pub fn kill_child(pid: u32) {
+    let mut children = CHILDREN.lock().unwrap();
+    let Some((name, child)) = children.remove(&pid) else { return };
+    record_children(&children);
+    drop(children);
+    if let Err(e) = child.kill() {
+        log::warn!("Failed to kill {name} ({pid}): {e}");
+    }
+}
+
+pub fn has_child(name: &str) -> bool {
+    CHILDREN.lock().unwrap().values().any(|(n, _)| n == name)
+}
+
+pub fn kill_children() {
+    let mut children = CHILDREN.lock().unwrap();
+    for (pid, (name, child)) in children.drain() {
+        if let Err(e) = child.kill() {
+            log::warn!("Failed to kill {name} ({pid}): {e}");
+        }
+    }
+    record_children(&children);
+}
+
+// A job that kills everything assigned to it once its last handle is gone,
+// which the system does for us however the app goes down
+#[cfg(target_os = "windows")]
+fn bind_child(pid: u32) {
+    use std::{ffi::c_void, sync::OnceLock};
+    use windows::{core::PCWSTR, Win32::{Foundation::{CloseHandle, HANDLE}, System::{
+        JobObjects::{
+            AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation, SetInformationJobObject,
+            JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        },
+        Threading::{OpenProcess, PROCESS_SET_QUOTA, PROCESS_TERMINATE},
+    }}};
+    static JOB: OnceLock<Option<usize>> = OnceLock::new();
+    let job = JOB.get_or_init(|| unsafe {
+        let job = CreateJobObjectW(None, PCWSTR::null())
+            .map_err(|e| log::warn!("Failed to create job object: {e}")).ok()?;
+        let mut info = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        SetInformationJobObject(
+            job, JobObjectExtendedLimitInformation,
+            &info as *const _ as *const c_void,
+            std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        ).map_err(|e| log::warn!("Failed to configure job object: {e}")).ok()?;
+        Some(job.0 as usize)
+    });
+    let Some(job) = job else { return };
+    unsafe {
+        let process = match OpenProcess(PROCESS_SET_QUOTA | PROCESS_TERMINATE, false, pid) {
+            Ok(process) => process,
+            Err(e) => {
+                log::warn!("Failed to open process {pid}: {e}");
+                return;
+            },
+        };
+        if let Err(e) = AssignProcessToJobObject(HANDLE(*job as *mut c_void), process) {
+            log::warn!("Failed to bind process {pid} to the app: {e}");
+        }
+        let _ = CloseHandle(process);
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn record_children(_: &HashMap<u32, (String, CommandChild)>) {}
+
+#[cfg(target_os = "windows")]
+pub fn reap_orphans() {}
+
+// Unix has no portable way to tie a child to its parent, so running children are
+// written down and whatever a crashed run left behind is reaped on the next launch
+#[cfg(unix)]
+fn bind_child(_: u32) {}
+
+#[cfg(unix)]
+fn record_children(children: &HashMap<u32, (String, CommandChild)>) {
+    let path = WORKING_PATH.join("children.pid");
+    let content: String = children.iter().map(|(pid, (name, _))| format!("{pid} {name}\n")).collect();
+    if let Err(e) = std::fs::write(&path, content) {
+        log::warn!("Failed to record child processes: {e}");
+    }
+}
+
+#[cfg(unix)]
+pub fn reap_orphans() {
+    let path = WORKING_PATH.join("children.pid");
+    let Ok(content) = std::fs::read_to_string(&path) else { return };
+    for line in content.lines() {
+        let Some((Ok(pid), name)) = line.split_once(' ').map(|(pid, name)| (pid.parse::<i32>(), name)) else {
+            continue;
+        };
+        // The pid may have been reused by an unrelated process since
+        if !process_name(pid).is_some_and(|v| v.starts_with(name)) {
+            continue;
+        }
+        if unsafe { libc::kill(pid, libc::SIGKILL) } == 0 {
+            log::warn!("Killed {name} ({pid}) left behind by a previous run");
+        }
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(target_os = "linux")]
+fn process_name(pid: i32) -> Option<String> {
+    // comm is cut at 15 bytes, so callers only compare prefixes
+    std::fs::read_to_string(format!("/proc/{pid}/comm")).ok().map(|v| v.trim().to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn process_name(pid: i32) -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let len = unsafe { libc::proc_name(pid, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len() as u32) };
+    (len > 0).then(|| String::from_utf8_lossy(&buffer[..len as usize]).into_owned())
+}
+
+// Paths that are about to be created are measured on the closest existing ancestor
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors().find(|v| v.exists()).unwrap_or(path)
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub fn available_space(path: &Path) -> Result<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    let path = CString::new(existing_ancestor(path).as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(target_os = "windows")]
+pub fn available_space(path: &Path) -> Result<u64> {
+    use windows::{core::HSTRING, Win32::Storage::FileSystem::GetDiskFreeSpaceExW};
+    let path = HSTRING::from(existing_ancestor(path).as_os_str());
+    let mut available = 0u64;
+    unsafe { GetDiskFreeSpaceExW(&path, Some(&mut available as *mut u64), None, None)? };
+    Ok(available)
+}
+
+#[cfg(unix)]
+pub fn same_volume(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let dev = |v: &Path| std::fs::metadata(existing_ancestor(v)).map(|m| m.dev()).ok();
+    dev(a).is_some_and(|v| Some(v) == dev(b))
+}
+
+#[cfg(target_os = "windows")]
+pub fn same_volume(a: &Path, b: &Path) -> bool {
+    volume_id(a).is_some_and(|v| Some(v) == volume_id(b))
+}
+
+// Mounted folders and UNC shares are volumes of their own, which the drive prefix doesn't tell
+#[cfg(target_os = "windows")]
+fn volume_id(path: &Path) -> Option<String> {
+    use windows::{core::HSTRING, Win32::Storage::FileSystem::{GetVolumeNameForVolumeMountPointW, GetVolumePathNameW}};
+    let terminated = |buffer: &[u16]| String::from_utf16_lossy(&buffer[..buffer.iter().position(|v| *v == 0).unwrap_or(buffer.len())]);
+    let path = HSTRING::from(existing_ancestor(path).as_os_str());
+    let mut mount = [0u16; 1024];
+    unsafe { GetVolumePathNameW(&path, &mut mount) }.ok()?;
+    let mount = terminated(&mount);
+    let mut volume = [0u16; 64];
+    // Network shares have no volume GUID, so their mount point has to do
+    match unsafe { GetVolumeNameForVolumeMountPointW(&HSTRING::from(mount.as_str()), &mut volume) } {
+        Ok(_) => Some(terminated(&volume)),
+        Err(_) => Some(mount.to_lowercase()),
+    }
+}
+
 pub fn process_err<T: ToString>(e: T, name: &str) -> T {
     let app = get_app_handle();
     while !*READY.read().unwrap() {
@@ -1,10 +1,11 @@
 use anyhow::{anyhow, Context, Result};
 use serde::{Serialize, Deserialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use serde_json::{Value, json};
 use tauri::async_runtime;
 use tauri_specta::Event;
 use std::path::PathBuf;
+use chrono::NaiveTime;
 use specta::Type;
 
 use sea_orm::{
@@ -17,7 +18,7 @@ use sea_orm::{
     },
 };
 
-use crate::{shared::{
+use crate::{aria2c, shared::{
     get_app_handle, Theme, CONFIG, DATABASE_URL, SECRET
 }, TauriResult};
 
@@ -32,8 +33,20 @@ pub struct Model {
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult, Type, Event)]
 pub struct Settings {
     pub max_conc: usize,
+    pub max_retry: usize,
+    pub download_backend: DownloadBackend,
+    pub aria2_rpc: SettingsAria2Rpc,
+    pub aria2: SettingsAria2,
+    pub speed_limit: SettingsSpeedLimit,
     pub temp_dir: PathBuf,
+    pub temp_gc: SettingsTempGc,
     pub down_dir: PathBuf,
+    pub min_free_space: u64,
+    pub verify: VerifyMode,
+    pub chapters: ChapterMode,
+    pub mux: SettingsMux,
+    pub burn: SettingsBurn,
+    pub profiles: Vec<TranscodeProfile>,
     pub df_dms: usize,
     pub df_ads: usize,
     pub df_cdc: usize,
@@ -45,6 +58,123 @@ pub struct Settings {
     pub language: String
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadBackend {
+    #[default]
+    Aria2,
+    Native,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult, Type, Event)]
+pub struct SettingsAria2Rpc {
+    pub external: bool,
+    pub url: String,
+    pub secret: String,
+    pub remote_dir: String,
+    pub local_dir: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult, Type, Event)]
+pub struct SettingsAria2 {
+    pub split: u32,
+    pub max_connection_per_server: u32,
+    pub min_split_size: u64,
+    pub max_tries: u32,
+    pub file_allocation: FileAllocation,
+    pub disk_cache: u64,
+    pub lowest_speed_limit: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAllocation {
+    None,
+    Prealloc,
+    Trunc,
+    Falloc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyMode {
+    Off,
+    Size,
+    Decode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum ChapterMode {
+    Off,
+    Embed,
+    Split,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult, Type, Event)]
+pub struct SettingsMux {
+    pub subtitles: bool,
+    pub danmaku: bool,
+    pub default: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult, Type, Event)]
+pub struct SettingsBurn {
+    pub source: BurnSource,
+    pub crf: u8,
+    pub preset: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum BurnSource {
+    Off,
+    Subtitle,
+    Danmaku,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct TranscodeProfile {
+    pub name: String,
+    pub video_codec: VideoCodec,
+    pub crf: u8,
+    pub bitrate: u32,
+    pub preset: String,
+    pub max_height: u32,
+    pub audio_codec: AudioCodec,
+    pub audio_bitrate: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    Copy,
+    H264,
+    Hevc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioCodec {
+    Copy,
+    Aac,
+    Opus,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult, Type, Event)]
+pub struct SettingsTempGc {
+    pub mode: TempGcMode,
+    pub age: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "lowercase")]
+pub enum TempGcMode {
+    Off,
+    Report,
+    Delete,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult, Type, Event)]
 pub struct SettingsProxy {
     pub addr: String,
@@ -58,6 +188,131 @@ pub struct SettingsAdvanced {
     pub filename_format: String,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult, Type, Event)]
+pub struct SettingsSpeedLimit {
+    pub global: u64,
+    pub schedule: Vec<SpeedLimitRule>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct SpeedLimitRule {
+    pub start: String,
+    pub end: String,
+    pub limit: u64,
+}
+
+impl SettingsAria2 {
+    pub fn validate(&self) -> Result<()> {
+        const MIB: u64 = 1024 * 1024;
+        if self.split < 1 {
+            return Err(anyhow!("aria2 split must be at least 1"));
+        }
+        if !(1..=16).contains(&self.max_connection_per_server) {
+            return Err(anyhow!("aria2 max-connection-per-server must be between 1 and 16"));
+        }
+        if !(MIB..=1024 * MIB).contains(&self.min_split_size) {
+            return Err(anyhow!("aria2 min-split-size must be between 1MiB and 1024MiB"));
+        }
+        Ok(())
+    }
+    // disk-cache is not accepted by changeGlobalOption, so it only applies at launch
+    pub fn options(&self, launch: bool) -> Vec<(&'static str, String)> {
+        let file_allocation = match self.file_allocation {
+            FileAllocation::None => "none",
+            FileAllocation::Prealloc => "prealloc",
+            FileAllocation::Trunc => "trunc",
+            FileAllocation::Falloc => "falloc",
+        };
+        let mut options = vec![
+            ("split", self.split.to_string()),
+            ("max-connection-per-server", self.max_connection_per_server.to_string()),
+            ("min-split-size", self.min_split_size.to_string()),
+            ("max-tries", self.max_tries.to_string()),
+            ("file-allocation", file_allocation.into()),
+            ("lowest-speed-limit", self.lowest_speed_limit.to_string()),
+        ];
+        if launch {
+            options.push(("disk-cache", self.disk_cache.to_string()));
+        }
+        options
+    }
+}
+
+impl SettingsTempGc {
+    pub fn validate(&self) -> Result<()> {
+        // Folders younger than this may still be in use by a task being added
+        if self.age < 1 {
+            return Err(anyhow!("Temp cleanup age must be at least 1 hour"));
+        }
+        Ok(())
+    }
+}
+
+// Shared by x264 and x265
+const PRESETS: [&str; 9] = [
+    "ultrafast", "superfast", "veryfast", "faster", "fast", "medium", "slow", "slower", "veryslow"
+];
+
+impl SettingsBurn {
+    pub fn validate(&self) -> Result<()> {
+        if self.crf > 51 {
+            return Err(anyhow!("x264 CRF must be between 0 and 51"));
+        }
+        if !PRESETS.contains(&self.preset.as_str()) {
+            return Err(anyhow!("Unknown x264 preset {}", self.preset));
+        }
+        Ok(())
+    }
+}
+
+impl TranscodeProfile {
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("Transcode profile name can't be empty"));
+        }
+        if self.crf > 51 {
+            return Err(anyhow!("Profile {}: CRF must be between 0 and 51", self.name));
+        }
+        if !PRESETS.contains(&self.preset.as_str()) {
+            return Err(anyhow!("Profile {}: unknown preset {}", self.name, self.preset));
+        }
+        if self.max_height % 2 != 0 {
+            return Err(anyhow!("Profile {}: max height must be even", self.name));
+        }
+        Ok(())
+    }
+}
+
+impl SettingsSpeedLimit {
+    pub fn validate(&self) -> Result<()> {
+        for rule in &self.schedule {
+            let (start, end) = rule.range()
+                .ok_or(anyhow!("Speed limit schedule times must be HH:MM, got {} - {}", rule.start, rule.end))?;
+            if start == end {
+                return Err(anyhow!("Speed limit schedule {} - {} is empty", rule.start, rule.end));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SpeedLimitRule {
+    fn range(&self) -> Option<(NaiveTime, NaiveTime)> {
+        let parse = |v: &str| NaiveTime::parse_from_str(v, "%H:%M").ok();
+        Some((parse(&self.start)?, parse(&self.end)?))
+    }
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        let Some((start, end)) = self.range() else {
+            return false;
+        };
+        if start <= end {
+            start <= time && time < end
+        } else { // Spans midnight
+            time >= start || time < end
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
 
@@ -110,27 +365,45 @@ pub async fn rw_config(action: &str, settings: Option<BTreeMap<String, Value>>,
     let update_config = |source: BTreeMap<String, Value>| {
         let mut config = CONFIG.write().unwrap();
         let mut config_json = serde_json::to_value(&*config)?;
+        let mut changes = Vec::new();
         if let Value::Object(ref mut config_obj) = config_json {
             for (key, value) in source {
                 if let (Some(Value::Object(original)), Value::Object(new)) = (config_obj.get_mut(&key), &value) {
                     for (_key, _value) in new {
                         original.insert(_key.clone(), _value.clone());
                     }
-                    let merged = json!(original);
-                    async_runtime::spawn(async move {
-                        let _ = insert(key, merged).await;
-                    });
+                    changes.push((key, json!(original)));
                 } else {
                     config_obj.insert(key.clone(), value.clone());
-                    async_runtime::spawn(async move {
-                        let _ = insert(key, value).await;
-                    });
+                    changes.push((key, value));
                 }
             }
         }
-        *config = serde_json::from_value(config_json)?;
+        let new_config: Settings = serde_json::from_value(config_json)?;
+        // Reject invalid values before anything is persisted
+        if new_config.max_retry > 10 {
+            return Err(anyhow!("Max retries must be between 0 and 10"));
+        }
+        new_config.aria2.validate()?;
+        new_config.speed_limit.validate()?;
+        new_config.temp_gc.validate()?;
+        new_config.burn.validate()?;
+        let mut names = HashSet::new();
+        for profile in &new_config.profiles {
+            profile.validate()?;
+            if !names.insert(&profile.name) {
+                return Err(anyhow!("Duplicate transcode profile {}", profile.name));
+            }
+        }
+        for (key, value) in changes {
+            async_runtime::spawn(async move {
+                let _ = insert(key, value).await;
+            });
+        }
+        *config = new_config;
         Ok::<(), anyhow::Error>(())
     };
+    let previous = CONFIG.read().unwrap().aria2.clone();
     if action == "init" || action == "read" {
         update_config(load().await?)?;
     } else if action == "write" {
@@ -146,6 +419,11 @@ pub async fn rw_config(action: &str, settings: Option<BTreeMap<String, Value>>,
         #[cfg(debug_assertions)]
         log::info!("{:?}", config);
     }
+    if action == "write" && config.aria2 != previous {
+        if let Err(e) = aria2c::apply_options().await {
+            log::warn!("Failed to apply aria2 options: {e}");
+        }
+    }
     config.emit(&get_app_handle()).unwrap();
     Ok(())
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 pub mod cookies;
 pub mod downloads;
 pub mod config;
+pub mod queue;
 mod migrate;
 
 pub async fn init() -> anyhow::Result<()> {
@@ -8,5 +9,6 @@ pub async fn init() -> anyhow::Result<()> {
     config::init().await?;
     cookies::init().await?;
     downloads::init().await?;
+    queue::init().await?;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,107 @@
+use serde::{Serialize, Deserialize};
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::{collections::VecDeque, sync::Arc};
+
+use sea_orm::{
+    Database, DbBackend, JsonValue, QueryOrder, Schema, Set, Statement, TransactionTrait,
+    entity::prelude::*,
+    sea_query::{
+        TableCreateStatement,
+        SqliteQueryBuilder,
+        OnConflict,
+    },
+};
+
+use crate::{
+    aria2c::{QueueInfo, QueueType},
+    shared::DATABASE_URL
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "queue")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    name: String,
+    queue: String,
+    position: i64,
+    value: QueueInfo,
+}
+
+#[derive(Debug, FromQueryResult)]
+struct RawModel {
+    name: String,
+    queue: String,
+    value: JsonValue,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+fn queue_name(queue_type: QueueType) -> String {
+    json!(queue_type).as_str().unwrap_or_default().to_string()
+}
+
+pub async fn init() -> Result<()> {
+    let db = Database::connect(&*DATABASE_URL)
+        .await.context("Failed to connect to the database")?;
+    let schema = Schema::new(DbBackend::Sqlite);
+    let stmt: TableCreateStatement = schema.create_table_from_entity(Entity).if_not_exists().to_owned();
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        stmt.to_string(SqliteQueryBuilder)
+    )).await.context("Failed to init Queue")?;
+    Ok(())
+}
+
+pub async fn load() -> Result<Vec<(QueueType, QueueInfo)>> {
+    let db = Database::connect(&*DATABASE_URL)
+        .await.context("Failed to connect to the database")?;
+    // Rows are decoded one by one so that a single broken item can't hold back the rest
+    let rows = Entity::find()
+        .order_by_asc(Column::Position)
+        .into_model::<RawModel>()
+        .all(&db).await.context("Failed to load Queue")?;
+    let mut result = Vec::new();
+    for row in rows {
+        let Ok(queue_type) = serde_json::from_value::<QueueType>(json!(row.queue)) else {
+            log::warn!("Skipping queue item {} with unknown type {}", row.name, row.queue);
+            continue;
+        };
+        match serde_json::from_value::<QueueInfo>(row.value) {
+            Ok(info) => result.push((queue_type, info)),
+            Err(e) => log::warn!("Skipping queue item {} that can't be decoded: {e}", row.name),
+        }
+    }
+    Ok(result)
+}
+
+pub async fn save(queue_type: QueueType, infos: &VecDeque<Arc<QueueInfo>>) -> Result<()> {
+    let db = Database::connect(&*DATABASE_URL)
+        .await.context("Failed to connect to the database")?;
+    let queue = queue_name(queue_type);
+    let txn = db.begin().await?;
+    Entity::delete_many()
+        .filter(Column::Queue.eq(&queue))
+        .exec(&txn).await
+        .with_context(|| format!("Failed to clear Queue: {}", queue))?;
+    if !infos.is_empty() {
+        let models = infos.iter().enumerate().map(|(i, info)| ActiveModel {
+            name: Set(info.id.clone()),
+            queue: Set(queue.clone()),
+            position: Set(i as i64),
+            value: Set((**info).clone()),
+        });
+        Entity::insert_many(models)
+            .on_conflict(
+            OnConflict::column(Column::Name)
+                .update_columns([Column::Queue, Column::Position, Column::Value])
+                .to_owned())
+            .exec(&txn).await
+            .with_context(|| format!("Failed to save Queue: {}", queue))?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
@@ -120,17 +120,17 @@ async setTheme(theme: Theme, modify: boolean) : Promise<Result<Theme, TauriError
     else return { status: "error", error: e  as any };
 }
 },
-async pushBackQueue(info: ArchiveInfo, select: CurrentSelect, tasks: Task[], parent: string | null) : Promise<Result<string, TauriError>> {
+async pushBackQueue(info: ArchiveInfo, select: CurrentSelect, tasks: Task[], parent: string | null, speedLimit: number | null) : Promise<Result<string, TauriError>> {
     try {
-    return { status: "ok", data: await TAURI_INVOKE("push_back_queue", { info, select, tasks, parent }) };
+    return { status: "ok", data: await TAURI_INVOKE("push_back_queue", { info, select, tasks, parent, speedLimit }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
 }
 },
-async processQueue(event: TAURI_CHANNEL<DownloadEvent>) : Promise<Result<null, TauriError>> {
+async processQueue() : Promise<Result<null, TauriError>> {
     try {
-    return { status: "ok", data: await TAURI_INVOKE("process_queue", { event }) };
+    return { status: "ok", data: await TAURI_INVOKE("process_queue") };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -144,9 +144,111 @@ async togglePause(pause: boolean, gid: string) : Promise<Result<null, TauriError
     else return { status: "error", error: e  as any };
 }
 },
-async removeTask(id: string, queueType: QueueType, gid: string | null) : Promise<Result<null, TauriError>> {
+async removeTask(id: string, queueType: QueueType) : Promise<Result<null, TauriError>> {
     try {
-    return { status: "ok", data: await TAURI_INVOKE("remove_task", { id, queueType, gid }) };
+    return { status: "ok", data: await TAURI_INVOKE("remove_task", { id, queueType }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async retryTask(id: string) : Promise<Result<null, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("retry_task", { id }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async retryAllFailed() : Promise<Result<null, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("retry_all_failed") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async setSpeedLimit(id: string, limit: number | null) : Promise<Result<null, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("set_speed_limit", { id, limit }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async moveTask(id: string, position: QueuePosition) : Promise<Result<null, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("move_task", { id, position }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async setPriority(id: string, priority: number) : Promise<Result<null, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("set_priority", { id, priority }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async pauseItem(id: string) : Promise<Result<null, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("pause_item", { id }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async resumeItem(id: string) : Promise<Result<null, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("resume_item", { id }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async pauseAll() : Promise<Result<null, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("pause_all") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async resumeAll() : Promise<Result<null, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("resume_all") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async getQueueState() : Promise<Result<QueueState, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("get_queue_state") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async subscribeProgress(event: TAURI_CHANNEL<DownloadEvent>) : Promise<number> {
+    return await TAURI_INVOKE("subscribe_progress", { event });
+},
+async unsubscribeProgress(id: number) : Promise<void> {
+    await TAURI_INVOKE("unsubscribe_progress", { id });
+},
+async cleanTemp(dryRun: boolean) : Promise<Result<TempOrphan[], TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("clean_temp", { dryRun }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async convert(id: string, input: string, profile: string) : Promise<Result<string, TauriError>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("convert", { id, input, profile }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -158,11 +260,15 @@ async removeTask(id: string, queueType: QueueType, gid: string | null) : Promise
 
 
 export const events = __makeEvents__<{
+aria2Health: Aria2Health,
+diskSpace: DiskSpace,
 headers: Headers,
 queueEvent: QueueEvent,
 settings: Settings,
 sidecarError: SidecarError
 }>({
+aria2Health: "aria2-health",
+diskSpace: "disk-space",
 headers: "headers",
 queueEvent: "queue-event",
 settings: "settings",
@@ -175,22 +281,48 @@ sidecarError: "sidecar-error"
 
 /** user-defined types **/
 
-export type ArchiveInfo = { title: string; cover: string; ts: Timestamp; output_dir: string; filename: string }
+export type ArchiveInfo = { title: string; cover: string; ts: Timestamp; output_dir: string; filename: string; metadata: MediaMetadata | null; chapters: Chapter[]; subtitles: SubtitleTrack[]; profile: TranscodeProfile | null }
+export type Aria2Health = { status: "Healthy"; port: number } | { status: "Restarting"; attempt: number; delay: number; reason: string }
+export type AudioCodec = "copy" | "aac" | "opus"
+export type BurnSource = "off" | "subtitle" | "danmaku"
+export type Chapter = { title: string; start: number; end: number }
+export type ChapterMode = "off" | "embed" | "split"
 export type CurrentSelect = { dms: number; ads: number; cdc: number; fmt: number }
-export type DownloadEvent = { status: "Started"; id: string; gid: string; taskType: TaskType } | { status: "Progress"; id: string; gid: string; contentLength: number; chunkLength: number } | { status: "Finished"; id: string; gid: string }
+export type DiskSpace = { status: "Low"; path: string; available: number; threshold: number } | { status: "Recovered" }
+export type DownloadBackend = "aria2" | "native"
+export type DownloadEvent = { status: "Started"; id: string; gid: string; taskType: TaskType } | { status: "Progress"; id: string; gid: string; contentLength: number; chunkLength: number; speed: number; eta: number | null; connections: number } | { status: "Aggregate"; id: string; progress: number; remaining: number } | { status: "Retrying"; id: string; gid: string; attempt: number; maxAttempts: number; code: number; message: string } | { status: "Finished"; id: string; gid: string } | { status: "Finalizing"; id: string; contentLength: number; chunkLength: number } | { status: "Verifying"; id: string } | { status: "Converting"; id: string; contentLength: number; chunkLength: number } | { status: "Cancelled"; id: string }
+export type FileAllocation = "none" | "prealloc" | "trunc" | "falloc"
 export type Headers = ({ [key in string]: string }) & { Cookie: string; "User-Agent": string; Referer: string; Origin: string }
 export type InitData = { version: string; hash: string; downloads: QueueInfo[] }
+export type ItemState = { stage: TaskStage | null; progress: number; remaining: number; tasks: TaskState[] }
 export type JsonValue = null | boolean | number | string | JsonValue[] | { [key in string]: JsonValue }
-export type QueueEvent = { type: "Waiting"; data: QueueInfo[] } | { type: "Doing"; data: QueueInfo[] } | { type: "Complete"; data: QueueInfo[] }
-export type QueueInfo = { id: string; tasks: Task[]; output: string; info: ArchiveInfo; select: CurrentSelect }
-export type QueueType = "waiting" | "doing" | "complete"
-export type Settings = { max_conc: number; temp_dir: string; down_dir: string; df_dms: number; df_ads: number; df_cdc: number; auto_check_update: boolean; auto_download: boolean; proxy: SettingsProxy; advanced: SettingsAdvanced; theme: Theme; language: string }
+export type MediaMetadata = { artist: string | null; date: string | null; description: string | null; url: string | null; album: string | null; episode: number | null; genre: string | null }
+export type QueueEvent = { type: "Waiting"; data: QueueInfo[] } | { type: "Doing"; data: QueueInfo[] } | { type: "Complete"; data: QueueInfo[] } | { type: "Failed"; data: QueueInfo[] } | { type: "Paused"; data: QueueInfo[] }
+export type QueueError = { code: number | null; message: string; taskType: TaskType }
+export type QueueInfo = { id: string; tasks: Task[]; output: string; info: ArchiveInfo; select: CurrentSelect; error: QueueError | null; speed_limit: number | null; priority: number; paused: boolean; size: number | null; verification: Verification | null }
+export type QueuePosition = "top" | "bottom" | { index: number }
+export type QueueState = { waiting: QueueInfo[]; doing: QueueInfo[]; complete: QueueInfo[]; failed: QueueInfo[]; paused: QueueInfo[]; progress: { [key in string]: ItemState } }
+export type QueueType = "waiting" | "doing" | "complete" | "failed" | "paused"
+export type Settings = { max_conc: number; max_retry: number; download_backend: DownloadBackend; aria2_rpc: SettingsAria2Rpc; aria2: SettingsAria2; speed_limit: SettingsSpeedLimit; temp_dir: string; temp_gc: SettingsTempGc; down_dir: string; min_free_space: number; verify: VerifyMode; chapters: ChapterMode; mux: SettingsMux; burn: SettingsBurn; profiles: TranscodeProfile[]; df_dms: number; df_ads: number; df_cdc: number; auto_check_update: boolean; auto_download: boolean; proxy: SettingsProxy; advanced: SettingsAdvanced; theme: Theme; language: string }
 export type SettingsAdvanced = { prefer_pb_danmaku: boolean; filename_format: string }
+export type SettingsAria2 = { split: number; max_connection_per_server: number; min_split_size: number; max_tries: number; file_allocation: FileAllocation; disk_cache: number; lowest_speed_limit: number }
+export type SettingsAria2Rpc = { external: boolean; url: string; secret: string; remote_dir: string; local_dir: string }
+export type SettingsBurn = { source: BurnSource; crf: number; preset: string }
+export type SettingsMux = { subtitles: boolean; danmaku: boolean; default: string }
 export type SettingsProxy = { addr: string; username: string; password: string }
+export type SettingsSpeedLimit = { global: number; schedule: SpeedLimitRule[] }
+export type SettingsTempGc = { mode: TempGcMode; age: number }
 export type SidecarError = { name: string; error: string }
-export type Task = { urls: string[] | null; gid: string | null; taskType: TaskType; path: string | null }
+export type SpeedLimitRule = { start: string; end: string; limit: number }
+export type SubtitleKind = "srt" | "danmaku"
+export type SubtitleTrack = { lang: string; title: string; kind: SubtitleKind; default: boolean; burn: boolean; data: string | null; path: string | null }
+export type Task = { urls: string[] | null; gid: string | null; taskType: TaskType; path: string | null; size: number | null; backend: DownloadBackend }
+export type TaskStage = "downloading" | "merging" | "converting"
+export type TaskState = { gid: string; taskType: TaskType; contentLength: number; chunkLength: number; speed: number; finished: boolean }
 export type TaskType = "video" | "audio" | "merge" | "flac"
 export type TauriError = { code: number | null; message: string }
+export type TempGcMode = "off" | "report" | "delete"
+export type TempOrphan = { path: string; size: number; age: number }
 export type Theme = 
 /**
  * Light theme.
@@ -205,6 +337,10 @@ export type Theme =
  */
 "auto"
 export type Timestamp = { millis: number; string: string }
+export type TranscodeProfile = { name: string; video_codec: VideoCodec; crf: number; bitrate: number; preset: string; max_height: number; audio_codec: AudioCodec; audio_bitrate: number }
+export type Verification = { size: number; expected: number | null; decoded: boolean }
+export type VerifyMode = "off" | "size" | "decode"
+export type VideoCodec = "copy" | "h264" | "hevc"
 
 /** tauri-specta globals **/
 
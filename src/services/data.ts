@@ -80,6 +80,7 @@ export async function getMediaInfo(id: string, type: Types.MediaType, options?:
                     cid: episode.cid,
                     duration: episode.page.duration,
                     ss_title: data.ugc_season.title,
+                    pubtime: episode.arc.pubdate,
                     index
                 })) :
                 data?.pages ? data.pages.map((page, index) => ({
@@ -91,6 +92,7 @@ export async function getMediaInfo(id: string, type: Types.MediaType, options?:
                     cid: page.cid,
                     duration: page.duration,
                     ss_title: data.title || page.part,
+                    pubtime: data.pubdate,
                     index
                 })) : [{
                     title: data.title,
@@ -101,6 +103,7 @@ export async function getMediaInfo(id: string, type: Types.MediaType, options?:
                     cid: data.cid,
                     duration: data.duration,
                     ss_title: data.title,
+                    pubtime: data.pubdate,
                     index: 0,
                 }]
         };
@@ -144,6 +147,8 @@ export async function getMediaInfo(id: string, type: Types.MediaType, options?:
                 ssid: data.season_id,
                 duration: episode.duration / 1000,
                 ss_title: data.season_title,
+                pubtime: episode.pub_time,
+                tags: data.styles,
                 index
             }))
         };
@@ -206,6 +211,7 @@ export async function getMediaInfo(id: string, type: Types.MediaType, options?:
                 cid: data.cid,
                 duration: data.duration,
                 ss_title: data.title,
+                pubtime: data.passtime,
                 index: 0,
             }]
         };
@@ -385,12 +391,20 @@ export async function pushBackQueue(params: {
     output_dir: string,
     index: number,
     output?: string,
+    profile?: string | null,
+    speed_limit?: number | null,
 }) {
     if (!params.video && !params.audio) throw new ApplicationError('No videos or audios found');
     const select = params.select;
     const newSelect = { dms: select.dms ?? -1, cdc: select.cdc ?? -1, ads: select.ads ?? -1, fmt: select.fmt ?? -1 };
     const ext = getFileExtension(newSelect);
     const info = params.info;
+    // FLV has no subtitle streams, so only tracks that are burned in are worth fetching there
+    const subtitles = params.video && params.audio
+        ? (await getSubtitleTracks(info)).filter(v => v.burn || ext !== 'flv') : [];
+    // Only merged outputs go through FFmpeg, where chapters are written
+    const chapters = useSettingsStore().chapters !== 'off' && params.video && params.audio
+        ? await getChapters(info, params.upper.mid ?? 0).catch(() => []) : [];
     const archiveInfo = {
         title: info.title,
         cover: info.cover,
@@ -400,6 +414,20 @@ export async function pushBackQueue(params: {
         },
         output_dir: safeName(params.output_dir),
         filename: `${filename(info, params.upper, params.index)}.${ext}`,
+        metadata: {
+            artist: params.upper.name ?? null,
+            date: info.pubtime ? new Date(info.pubtime * 1000).toISOString().slice(0, 10) : null,
+            description: info.desc || null,
+            url: info.epid ? `https://www.bilibili.com/bangumi/play/ep${info.epid}`
+                : info.sid ? `https://www.bilibili.com/audio/au${info.sid}`
+                : info.bvid ? `https://www.bilibili.com/video/${info.bvid}` : null,
+            album: info.ss_title || null,
+            episode: info.epid ? info.index + 1 : null,
+            genre: info.tags?.join(', ') || null,
+        },
+        chapters,
+        subtitles,
+        profile: useSettingsStore().profiles.find(v => v.name === params.profile) ?? null,
     };
     const tasks = [
         params.video && {
@@ -407,14 +435,16 @@ export async function pushBackQueue(params: {
                 params.video.baseUrl ?? params.video.base_url,
                 ...(params.video.backupUrl ?? params.video.backup_url ?? [])
             ],
-            taskType: 'video'
+            taskType: 'video',
+            size: params.video.size ?? null
         },
         params.audio && {
             urls: [
                 params.audio.baseUrl ?? params.audio.base_url,
                 ...(params.audio.backupUrl ?? params.audio.backup_url ?? [])
             ],
-            taskType: 'audio'
+            taskType: 'audio',
+            size: params.audio.size ?? null
         },
         (params.video && params.audio) && {
             taskType: 'merge'
@@ -424,7 +454,7 @@ export async function pushBackQueue(params: {
         }
     ].filter(Boolean) as any[];
     const result = await Backend.commands.pushBackQueue(
-        archiveInfo, newSelect, tasks, params.output ?? null,
+        archiveInfo, newSelect, tasks, params.output ?? null, params.speed_limit ?? null,
     );
     if (result.status === 'error') throw result.error;
     return result.data;
@@ -493,6 +523,66 @@ export async function getHistoryDanmaku(info: Types.MediaInfo["list"][0], date:
     return new TextEncoder().encode(xml);
 }
 
+export async function getChapters(info: Types.MediaInfo["list"][0], mid: number): Promise<Backend.Chapter[]> {
+    if (!info.aid) return [];
+    const playerInfo = await getPlayerInfo(info.aid, info.cid);
+    let chapters = playerInfo.view_points.filter(v => v.type === 2)
+        .map(v => ({ title: v.content, start: v.from, end: v.to }));
+    if (!chapters.length) {
+        const response = await tryFetch("https://api.bilibili.com/x/web-interface/view/conclusion/get", {
+            auth: 'wbi', params: { aid: info.aid, cid: info.cid ?? await getCid(info.aid), up_mid: mid }
+        });
+        const model_result = (response as Types.AISummaryInfo).data.model_result;
+        if (model_result.result_type !== 2) return [];
+        // Outline sections only carry their start, each one ends where the next begins
+        chapters = model_result.outline.map((section, index, outline) => ({
+            title: section.title,
+            start: section.timestamp,
+            end: outline[index + 1]?.timestamp ?? info.duration ?? section.timestamp,
+        }));
+    }
+    return chapters.filter(v => v.end > v.start);
+}
+
+const subtitleLanguages: Record<string, string> = {
+    zh: 'chi', en: 'eng', ja: 'jpn', ko: 'kor', es: 'spa', fr: 'fre', de: 'ger',
+    ru: 'rus', pt: 'por', ar: 'ara', it: 'ita', th: 'tha', vi: 'vie', id: 'ind',
+};
+
+export async function getSubtitleTracks(info: Types.MediaInfo["list"][0]): Promise<Backend.SubtitleTrack[]> {
+    const { mux, burn } = useSettingsStore();
+    const tracks: Backend.SubtitleTrack[] = [];
+    if (mux.subtitles || burn.source === 'subtitle') {
+        const subtitles = await getSubtitles(info).catch(() => []) ?? [];
+        subtitles.sort((a, b) => Number(a.lan.startsWith('ai-')) - Number(b.lan.startsWith('ai-')));
+        for (const subtitle of subtitles) {
+            const data = await getSubtitle(subtitle.subtitle_url).catch(() => null);
+            if (!data) continue;
+            const primary = subtitle.lan.replace(/^ai-/, '').split('-')[0];
+            tracks.push({
+                lang: subtitleLanguages[primary] ?? 'und', title: subtitle.lan_doc,
+                kind: 'srt', default: false, burn: false, data, path: null,
+            });
+        }
+    }
+    if (mux.danmaku || burn.source === 'danmaku') {
+        const body = await getLiveDanmaku(info).catch(() => null);
+        if (body) tracks.push({
+            lang: 'chi', title: 'Danmaku',
+            kind: 'danmaku', default: false, burn: false, data: new TextDecoder().decode(body), path: null,
+        });
+    }
+    // Human-made subtitles were sorted before AI ones, so they win the default
+    const target = mux.default === 'danmaku' ? tracks.find(v => v.kind === 'danmaku')
+        : tracks.find(v => v.kind === 'srt' && v.lang === subtitleLanguages[mux.default]);
+    if (target) target.default = true;
+    const burned = burn.source === 'danmaku' ? tracks.find(v => v.kind === 'danmaku')
+        : burn.source === 'subtitle' ? (target?.kind === 'srt' ? target : tracks.find(v => v.kind === 'srt')) : undefined;
+    if (burned) burned.burn = true;
+    // Tracks fetched only to be burned are not muxed as well
+    return tracks.filter(v => v.burn || (v.kind === 'srt' ? mux.subtitles : mux.danmaku));
+}
+
 export async function getPlayerInfo(id: number, cid?: number) {
     const params = { aid: id, cid: cid ?? await getCid(id) };
     const response = await tryFetch('https://api.bilibili.com/x/player/wbi/v2', { auth: 'wbi', params });
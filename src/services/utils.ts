@@ -80,6 +80,22 @@ export function setEventHook() {
         const type = e.payload.type.toLowerCase() as keyof typeof queue.$state;
         queue[type] = e.payload.data;
     })
+    events.aria2Health.listen(e => {
+        const health = e.payload;
+        if (health.status === 'Restarting') {
+            AppLog(i18n.global.t('error.aria2Restarting', [health.delay, health.attempt]) + ':\n' + health.reason, TYPE.WARNING);
+        } else {
+            AppLog(i18n.global.t('error.aria2Recovered'), TYPE.SUCCESS);
+        }
+    });
+    events.diskSpace.listen(e => {
+        const space = e.payload;
+        if (space.status === 'Low') {
+            AppLog(i18n.global.t('error.diskSpaceLow', [space.path, formatBytes(space.available)]), TYPE.WARNING);
+        } else {
+            AppLog(i18n.global.t('error.diskSpaceRecovered'), TYPE.SUCCESS);
+        }
+    });
     events.sidecarError.listen(e => {
         const err = e.payload;
         new ApplicationError(i18n.global.t('error.errorProvider', [err.name]) + ':\n' + err.error, { name: 'SidecarError' }).handleError();
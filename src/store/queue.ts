@@ -5,6 +5,8 @@ interface State {
     waiting: QueueInfo[],
     doing: QueueInfo[],
     complete: QueueInfo[],
+    failed: QueueInfo[],
+    paused: QueueInfo[],
 }
 
 export const useQueueStore = defineStore('queue', {
@@ -12,5 +14,7 @@ export const useQueueStore = defineStore('queue', {
         waiting: [],
         doing: [],
         complete: [],
+        failed: [],
+        paused: [],
     })
 });
\ No newline at end of file
@@ -5,8 +5,48 @@ import { useAppStore } from "./app";
 export const useSettingsStore = defineStore('settings', {
     state: (): Settings => ({
         down_dir: String(),
+        min_free_space: Number(),
+        verify: 'size',
+        chapters: 'off',
+        mux: {
+            subtitles: false,
+            danmaku: false,
+            default: 'none',
+        },
+        burn: {
+            source: 'off',
+            crf: Number(),
+            preset: String(),
+        },
+        profiles: [],
         temp_dir: String(),
+        temp_gc: {
+            mode: 'delete',
+            age: Number(),
+        },
         max_conc: Number(),
+        max_retry: Number(),
+        download_backend: 'aria2',
+        aria2_rpc: {
+            external: false,
+            url: String(),
+            secret: String(),
+            remote_dir: String(),
+            local_dir: String(),
+        },
+        aria2: {
+            split: Number(),
+            max_connection_per_server: Number(),
+            min_split_size: Number(),
+            max_tries: Number(),
+            file_allocation: 'prealloc',
+            disk_cache: Number(),
+            lowest_speed_limit: Number(),
+        },
+        speed_limit: {
+            global: Number(),
+            schedule: [],
+        },
         df_dms: Number(),
         df_ads: Number(),
         df_cdc: Number(),
@@ -148,7 +148,9 @@ export interface MediaInfo {
     ssid?: number;
     duration?: number;
     ss_title: string;
-    index: number
+    index: number;
+    pubtime?: number;
+    tags?: string[];
   }[]
 }
 
@@ -805,7 +807,13 @@ export interface PlayerInfo {
         ai_status: number;
       }[];
     };
-    view_points: unknown[];
+    view_points: {
+      type: number;
+      from: number;
+      to: number;
+      content: string;
+      imgUrl: string;
+    }[];
     preview_toast: string;
     interaction: {
       history_node: {